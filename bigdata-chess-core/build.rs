@@ -8,8 +8,19 @@ fn main() -> Result<()> {
         .type_attribute("chess.ChessGame", "#[derive(derive_builder::Builder)]")
         .type_attribute("chess.Player", "#[derive(derive_builder::Builder)]")
         .field_attribute("chess.Player.title", "#[builder(default)]")
+        .field_attribute("chess.Player.fide_id", "#[builder(default)]")
         .field_attribute("chess.ChessGame.rating_outcome_for_white", "#[builder(default)]")
         .field_attribute("chess.ChessGame.rating_outcome_for_black", "#[builder(default)]")
+        .field_attribute("chess.ChessGame.variant", "#[builder(default)]")
+        .field_attribute("chess.ChessGame.game_id", "#[builder(default)]")
+        .field_attribute("chess.ChessGame.starting_fen", "#[builder(default)]")
+        .field_attribute("chess.ChessGame.annotator", "#[builder(default)]")
+        .field_attribute("chess.ChessGame.extra_headers", "#[builder(default)]")
+        .field_attribute("chess.ChessGame.starting_position_hash", "#[builder(default)]")
+        .field_attribute("chess.ChessGame.rated", "#[builder(default)]")
+        .field_attribute("chess.ChessGame.speed", "#[builder(default)]")
+        .field_attribute("chess.ChessGame.tournament_id", "#[builder(default)]")
+        .field_attribute("chess.ChessGame.swiss_id", "#[builder(default)]")
         .protoc_arg("--experimental_allow_proto3_optional")
         .compile_protos(&["proto/chess.proto"], &["proto/"])?;
     Ok(())
@@ -0,0 +1,8 @@
+#!/bin/bash
+set -e
+
+# downloads opening tables from lichess chess-openings into this directory
+cd "$(dirname "$0")"
+for file in a b c d e; do
+  curl -sSfL -o $file.tsv https://raw.githubusercontent.com/lichess-org/chess-openings/master/$file.tsv
+done
@@ -0,0 +1,93 @@
+use crate::{
+    data::ChessGame,
+    eval::{Eval, Score, Side},
+};
+
+// evaluation of the standard starting position, used by lichess as the one before the first move
+const INITIAL_POSITION_CENTIPAWNS: i32 = 15;
+
+// lichess accuracy: each move gets accuracy from the drop of win probability of the side which made it. Game
+// accuracy is the average of mean of these weighted by volatility of the position and their harmonic mean.
+pub fn player_accuracy(game: &ChessGame, side: Side) -> Option<f64> {
+    let evals = game_evals(game);
+    // games without evaluations should not get accuracy from the starting position alone
+    if evals.len() < 2 {
+        return None;
+    }
+
+    let win_probabilities: Vec<f64> = evals.iter().map(|(_, eval)| eval.win_probability()).collect();
+    let window_size = (evals.len() / 10).clamp(2, 8);
+
+    // first moves are weighted by the first window
+    let mut windows: Vec<&[f64]> = vec![&win_probabilities[..window_size]; window_size - 2];
+    windows.extend(win_probabilities.windows(window_size));
+    let weights: Vec<f64> = windows.iter().map(|v| standard_deviation(v).clamp(0.5, 12.0)).collect();
+
+    let mut move_accuracies = Vec::new();
+    for (i, pair) in evals.windows(2).enumerate() {
+        let ((ply_before, before), (ply_after, after)) = (&pair[0], &pair[1]);
+        // evaluation of some position is missing, so the move cannot be judged
+        if ply_after - ply_before != 1 || before.side_to_move != side {
+            continue;
+        }
+
+        let weight = match weights.get(i) {
+            Some(v) => *v,
+            None => continue,
+        };
+        move_accuracies.push((move_accuracy(before.win_probability_for(side), after.win_probability_for(side)), weight));
+    }
+
+    if move_accuracies.is_empty() {
+        return None;
+    }
+
+    let weighted_mean = move_accuracies.iter().map(|(accuracy, weight)| accuracy * weight).sum::<f64>()
+        / move_accuracies.iter().map(|(_, weight)| weight).sum::<f64>();
+    let harmonic_mean = move_accuracies.len() as f64
+        / move_accuracies.iter().map(|(accuracy, _)| 1.0 / accuracy.max(1.0)).sum::<f64>();
+
+    Some((weighted_mean + harmonic_mean) / 2.0)
+}
+
+// win probabilities are in percents, for the side which made the move
+pub fn move_accuracy(win_probability_before: f64, win_probability_after: f64) -> f64 {
+    if win_probability_after >= win_probability_before {
+        return 100.0;
+    }
+
+    let win_probability_loss = win_probability_before - win_probability_after;
+    let accuracy = 103.1668100711649 * (-0.04354415386753951 * win_probability_loss).exp() - 3.166924740191411;
+    (accuracy + 1.0).clamp(0.0, 100.0)
+}
+
+// evaluations of positions in order, with number of moves made before them. Games from the standard starting position
+// also get its evaluation, so that the first move can be judged.
+fn game_evals(game: &ChessGame) -> Vec<(u32, Eval)> {
+    let first_to_move = Side::first_to_move(&game.starting_fen);
+
+    let mut evals = Vec::new();
+    if game.starting_fen.is_empty() {
+        evals.push((0, Eval {
+            score: Score::Centipawns(INITIAL_POSITION_CENTIPAWNS),
+            side_to_move: Side::White,
+        }));
+    }
+
+    let mut ply = 0;
+    for entry in &game.game_entries {
+        if entry.san.is_some() {
+            ply += 1;
+        } else if let Some(eval) = entry.comment.as_ref().and_then(|v| Eval::from_comment(v, Side::to_move_after(ply, first_to_move))) {
+            evals.push((ply, eval));
+        }
+    }
+
+    evals
+}
+
+fn standard_deviation(values: &[f64]) -> f64 {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    variance.sqrt()
+}
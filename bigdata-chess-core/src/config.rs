@@ -1,74 +1,194 @@
 use {
-    std::fs::read_to_string,
-    tracing::warn,
+    std::{env, fs::read_to_string, path::Path, time::Duration},
+    tracing::info,
     serde::Deserialize,
-    crate::queue::{TOPIC_LICHESS_RAW_GAMES, TOPIC_CHESS_GAMES},
+    anyhow::{anyhow, Result, Context},
+    toml::value::{Value, Table},
+    crate::{
+        lichess::{YearMonth, LichessVariant},
+        queue::{topic_for_variant, TOPIC_LICHESS_DATA_FILES, TOPIC_LICHESS_DATA_FILES_SYNCED, TOPIC_LICHESS_RAW_GAMES, TOPIC_LICHESS_RAW_GAMES_REJECTS, TOPIC_CHESS_GAMES, TOPIC_CHESS_GAME_JUDGEMENTS, TOPIC_CHESS_GAME_RATINGS, TOPIC_CHESS_GAME_PARSER_ERRORS, TOPIC_CHESS_GAME_PARSER_ERRORS_UNRESOLVED, TOPIC_CHUNK_SPLITTER_STATE},
+    },
 };
 
+const DEFAULT_CONFIG_PATHS: [&str; 2] = ["./config.toml", "/config/config.toml"];
+
+// BIGDATA_CHESS__INFRA__QUEUE__ENDPOINT=... overrides infra.queue.endpoint
+const ENV_PREFIX: &str = "BIGDATA_CHESS__";
+
 #[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default)]
     pub steps: StepsConfig,
     pub infra: Option<InfraConfig>,
 }
 
 #[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct StepsConfig {
+    #[serde(default)]
     pub update_checker: UpdateCheckerStepConfig,
     pub file_downloader: Option<FileDownloaderStepConfig>,
     pub chunk_splitter: Option<ChunkSplitterStepConfig>,
     #[serde(default)]
     pub game_parser: GameParserStepConfig,
     #[serde(default)]
+    pub replay_parser_errors: ReplayParserErrorsStepConfig,
+    #[serde(default)]
     pub postgres_import: PostgresImportStepConfig,
     #[serde(default)]
     pub storage_import: StorageImportStepConfig,
     #[serde(default)]
+    pub move_judgement: MoveJudgementStepConfig,
+    #[serde(default)]
+    pub opening_tree: OpeningTreeStepConfig,
+    #[serde(default)]
+    pub position_index: PositionIndexStepConfig,
+    #[serde(default)]
+    pub glicko2_ratings: Glicko2RatingsStepConfig,
+    #[serde(default)]
     hdfs_import: HdfsImportStepConfig,
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct UpdateCheckerStepConfig {
     pub enabled: bool,
+    variants: Option<Vec<LichessVariant>>,
+    from_month: Option<YearMonth>,
+    to_month: Option<YearMonth>,
+    newest: Option<usize>,
+    allow_list: Option<Vec<String>>,
+    max_total_bytes: Option<u64>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct FileDownloaderStepConfig {
     pub enabled: bool,
+    from_topic: Option<String>,
+    to_topic: Option<String>,
+    group_id: Option<String>,
+    chunk_size: Option<u64>,
+    max_poll_interval_secs: Option<u64>,
+    connect_timeout_secs: Option<u64>,
+    download_retries: Option<u32>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct ChunkSplitterStepConfig {
     pub enabled: bool,
     to_topic: Option<String>,
+    rejects_topic: Option<String>,
+    state_topic: Option<String>,
     group_id: Option<String>,
+    transactional_id: Option<String>,
+    prefetch_chunks: Option<usize>,
+    chunk_fetch_retries: Option<u32>,
+    max_poll_interval_secs: Option<u64>,
+    send_retries: Option<u32>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct GameParserStepConfig {
     pub enabled: bool,
     from_topic: Option<String>,
     to_topic: Option<String>,
     group_id: Option<String>,
+    workers: Option<usize>,
+    batch_size: Option<usize>,
+    transactional_id: Option<String>,
+    max_poll_interval_secs: Option<u64>,
+    send_retries: Option<u32>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ReplayParserErrorsStepConfig {
+    pub enabled: bool,
+    from_topic: Option<String>,
+    to_topic: Option<String>,
+    failed_topic: Option<String>,
+    group_id: Option<String>,
+    idle_timeout_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct PostgresImportStepConfig {
     pub enabled: bool,
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct StorageImportStepConfig {
     pub enabled: bool,
+    variant: Option<LichessVariant>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MoveJudgementStepConfig {
+    pub enabled: bool,
+    variant: Option<LichessVariant>,
+    to_topic: Option<String>,
+    group_id: Option<String>,
+    flush_games: Option<u64>,
+    max_poll_interval_secs: Option<u64>,
+    transactional_id: Option<String>,
+    send_retries: Option<u32>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct OpeningTreeStepConfig {
+    pub enabled: bool,
+    group_id: Option<String>,
+    database_path: Option<String>,
+    max_ply: Option<u32>,
+    rating_band_width: Option<u32>,
+    flush_games: Option<u64>,
+    max_poll_interval_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PositionIndexStepConfig {
+    pub enabled: bool,
+    variant: Option<LichessVariant>,
+    group_id: Option<String>,
+    database_path: Option<String>,
+    flush_games: Option<u64>,
+    max_poll_interval_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Glicko2RatingsStepConfig {
+    pub enabled: bool,
+    variant: Option<LichessVariant>,
+    to_topic: Option<String>,
+    group_id: Option<String>,
+    database_path: Option<String>,
+    reorder_window_secs: Option<u64>,
+    flush_games: Option<u64>,
+    max_poll_interval_secs: Option<u64>,
+    transactional_id: Option<String>,
+    send_retries: Option<u32>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct HdfsImportStepConfig {
-    enabled: bool,
+    pub enabled: bool,
     synced_games_files_limit: Option<u32>,
     synced_game_moves_files_limit: Option<u32>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct InfraConfig {
     queue: Option<QueueConfig>,
     storage: Option<StorageConfig>,
@@ -77,21 +197,28 @@ pub struct InfraConfig {
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct QueueConfig {
     pub endpoint: String,
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct StorageConfig {
     endpoint: Option<String>,
     access_key: Option<String>,
+    access_key_file: Option<String>,
     secret_key: Option<String>,
+    secret_key_file: Option<String>,
     remote_api_key: Option<String>,
+    remote_api_key_file: Option<String>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct DatabaseConfig {
     connection_string: Option<String>,
+    connection_string_file: Option<String>,
 }
 
 impl Default for Config {
@@ -110,8 +237,13 @@ impl Default for StepsConfig {
             file_downloader: None,
             chunk_splitter: None,
             game_parser: GameParserStepConfig::default(),
+            replay_parser_errors: ReplayParserErrorsStepConfig::default(),
             postgres_import: PostgresImportStepConfig::default(),
             storage_import: StorageImportStepConfig::default(),
+            move_judgement: MoveJudgementStepConfig::default(),
+            opening_tree: OpeningTreeStepConfig::default(),
+            position_index: PositionIndexStepConfig::default(),
+            glicko2_ratings: Glicko2RatingsStepConfig::default(),
             hdfs_import: HdfsImportStepConfig::default(),
         }
     }
@@ -121,16 +253,106 @@ impl Default for UpdateCheckerStepConfig {
     fn default() -> Self {
         Self {
             enabled: false,
+            variants: None,
+            from_month: None,
+            to_month: None,
+            newest: None,
+            allow_list: None,
+            max_total_bytes: None,
+        }
+    }
+}
+
+impl UpdateCheckerStepConfig {
+    pub fn variants(&self) -> Vec<LichessVariant> {
+        self.variants.as_ref().cloned().unwrap_or(vec![LichessVariant::Standard])
+    }
+
+    pub fn from_month(&self) -> Option<&YearMonth> {
+        self.from_month.as_ref()
+    }
+
+    pub fn to_month(&self) -> Option<&YearMonth> {
+        self.to_month.as_ref()
+    }
+
+    // when no selection policy is configured, only the newest file is downloaded
+    pub fn newest(&self) -> Option<usize> {
+        if self.from_month.is_none() && self.to_month.is_none() && self.allow_list.is_none() && self.max_total_bytes.is_none() {
+            Some(self.newest.unwrap_or(1))
+        } else {
+            self.newest
+        }
+    }
+
+    pub fn allow_list(&self) -> Option<&Vec<String>> {
+        self.allow_list.as_ref()
+    }
+
+    pub fn max_total_bytes(&self) -> Option<u64> {
+        self.max_total_bytes
+    }
+}
+
+impl Default for FileDownloaderStepConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            from_topic: None,
+            to_topic: None,
+            group_id: None,
+            chunk_size: None,
+            max_poll_interval_secs: None,
+            connect_timeout_secs: None,
+            download_retries: None,
         }
     }
 }
 
+impl FileDownloaderStepConfig {
+    pub fn from_topic(&self) -> String {
+        self.from_topic.as_ref().map(|v| v.to_owned()).unwrap_or(TOPIC_LICHESS_DATA_FILES.to_owned())
+    }
+
+    pub fn to_topic(&self) -> String {
+        self.to_topic.as_ref().map(|v| v.to_owned()).unwrap_or(TOPIC_LICHESS_DATA_FILES_SYNCED.to_owned())
+    }
+
+    pub fn group_id(&self) -> String {
+        self.group_id.as_ref().map(|v| v.to_owned()).unwrap_or("bigdata-chess-file-downloader".to_owned())
+    }
+
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size.unwrap_or(100 * 1024 * 1024)
+    }
+
+    // downloading file takes a lot of time
+    pub fn max_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.max_poll_interval_secs.unwrap_or(3000))
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs.unwrap_or(30))
+    }
+
+    pub fn download_retries(&self) -> u32 {
+        self.download_retries.unwrap_or(5)
+    }
+}
+
 impl Default for ChunkSplitterStepConfig {
     fn default() -> Self {
         Self {
             enabled: false,
             to_topic: None,
+            rejects_topic: None,
+            state_topic: None,
             group_id: None,
+            transactional_id: None,
+            prefetch_chunks: None,
+            chunk_fetch_retries: None,
+            max_poll_interval_secs: None,
+            send_retries: None,
         }
     }
 }
@@ -140,9 +362,43 @@ impl ChunkSplitterStepConfig {
         self.to_topic.as_ref().map(|v| v.to_owned()).unwrap_or(TOPIC_LICHESS_RAW_GAMES.to_owned())
     }
 
+    // fragments of data files which do not look like a pgn game go here, with variant suffix for non-standard variants
+    pub fn rejects_topic(&self) -> String {
+        self.rejects_topic.as_ref().map(|v| v.to_owned()).unwrap_or(TOPIC_LICHESS_RAW_GAMES_REJECTS.to_owned())
+    }
+
+    pub fn state_topic(&self) -> String {
+        self.state_topic.as_ref().map(|v| v.to_owned()).unwrap_or(TOPIC_CHUNK_SPLITTER_STATE.to_owned())
+    }
+
     pub fn group_id(&self) -> String {
         self.group_id.as_ref().map(|v| v.to_owned()).unwrap_or("bigdata-chess-chunk-splitter".to_owned())
     }
+
+    // has to be stable across restarts, so that transactions of previous instance are fenced off.
+    // Each running instance needs its own one.
+    pub fn transactional_id(&self) -> String {
+        self.transactional_id.as_ref().map(|v| v.to_owned()).unwrap_or("bigdata-chess-chunk-splitter".to_owned())
+    }
+
+    // how many chunks of data file are fetched from object storage ahead of decompression
+    pub fn prefetch_chunks(&self) -> usize {
+        self.prefetch_chunks.unwrap_or(2)
+    }
+
+    pub fn chunk_fetch_retries(&self) -> u32 {
+        self.chunk_fetch_retries.unwrap_or(5)
+    }
+
+    // consumer is not polled while a file is being split, so this has to be longer than splitting the largest file takes
+    pub fn max_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.max_poll_interval_secs.unwrap_or(3000))
+    }
+
+    // how many times a batch is sent again after its transaction is aborted, before the step fails
+    pub fn send_retries(&self) -> u32 {
+        self.send_retries.unwrap_or(5)
+    }
 }
 
 impl Default for GameParserStepConfig {
@@ -152,6 +408,11 @@ impl Default for GameParserStepConfig {
             from_topic: None,
             to_topic: None,
             group_id: None,
+            workers: None,
+            batch_size: None,
+            transactional_id: None,
+            max_poll_interval_secs: None,
+            send_retries: None,
         }
     }
 }
@@ -168,6 +429,68 @@ impl GameParserStepConfig {
     pub fn group_id(&self) -> String {
         self.group_id.as_ref().map(|v| v.to_owned()).unwrap_or("bigdata-chess-game-parser".to_owned())
     }
+
+    // number of games parsed concurrently, defaults to number of cpus
+    pub fn workers(&self) -> usize {
+        self.workers.unwrap_or_else(|| std::thread::available_parallelism().map(|v| v.get()).unwrap_or(1)).max(1)
+    }
+
+    // games are produced and offsets committed in batches of this size
+    pub fn batch_size(&self) -> usize {
+        self.batch_size.unwrap_or(256).max(1)
+    }
+
+    // has to be stable across restarts, so that transactions of previous instance are fenced off.
+    // Each running instance needs its own one.
+    pub fn transactional_id(&self) -> String {
+        self.transactional_id.as_ref().map(|v| v.to_owned()).unwrap_or("bigdata-chess-game-parser".to_owned())
+    }
+
+    pub fn max_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.max_poll_interval_secs.unwrap_or(300))
+    }
+
+    // how many times a batch is sent again after its transaction is aborted, before the step fails
+    pub fn send_retries(&self) -> u32 {
+        self.send_retries.unwrap_or(5)
+    }
+}
+
+impl Default for ReplayParserErrorsStepConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            from_topic: None,
+            to_topic: None,
+            failed_topic: None,
+            group_id: None,
+            idle_timeout_secs: None,
+        }
+    }
+}
+
+impl ReplayParserErrorsStepConfig {
+    pub fn from_topic(&self) -> String {
+        self.from_topic.as_ref().map(|v| v.to_owned()).unwrap_or(TOPIC_CHESS_GAME_PARSER_ERRORS.to_owned())
+    }
+
+    pub fn to_topic(&self) -> String {
+        self.to_topic.as_ref().map(|v| v.to_owned()).unwrap_or(TOPIC_CHESS_GAMES.to_owned())
+    }
+
+    // games which still fail to parse go here
+    pub fn failed_topic(&self) -> String {
+        self.failed_topic.as_ref().map(|v| v.to_owned()).unwrap_or(TOPIC_CHESS_GAME_PARSER_ERRORS_UNRESOLVED.to_owned())
+    }
+
+    pub fn group_id(&self) -> String {
+        self.group_id.as_ref().map(|v| v.to_owned()).unwrap_or("bigdata-chess-replay-parser-errors".to_owned())
+    }
+
+    // step finishes when there are no new errors to replay for this long
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs.unwrap_or(60))
+    }
 }
 
 impl Default for PostgresImportStepConfig {
@@ -182,10 +505,256 @@ impl Default for StorageImportStepConfig {
     fn default() -> Self {
         Self {
             enabled: false,
+            variant: None,
+        }
+    }
+}
+
+impl StorageImportStepConfig {
+    pub fn variant(&self) -> LichessVariant {
+        self.variant.unwrap_or(LichessVariant::Standard)
+    }
+
+    pub fn from_topic(&self) -> String {
+        topic_for_variant(TOPIC_CHESS_GAMES, self.variant())
+    }
+
+    pub fn group_id(&self) -> String {
+        match self.variant() {
+            LichessVariant::Standard => "bigdata-chess-storage-import".to_owned(),
+            other => format!("bigdata-chess-storage-import-{}", other.name()),
+        }
+    }
+}
+
+impl Default for MoveJudgementStepConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            variant: None,
+            to_topic: None,
+            group_id: None,
+            flush_games: None,
+            max_poll_interval_secs: None,
+            transactional_id: None,
+            send_retries: None,
+        }
+    }
+}
+
+impl MoveJudgementStepConfig {
+    pub fn variant(&self) -> LichessVariant {
+        self.variant.unwrap_or(LichessVariant::Standard)
+    }
+
+    pub fn from_topic(&self) -> String {
+        topic_for_variant(TOPIC_CHESS_GAMES, self.variant())
+    }
+
+    // per-game summaries
+    pub fn to_topic(&self) -> String {
+        topic_for_variant(&self.to_topic.as_ref().map(|v| v.to_owned()).unwrap_or(TOPIC_CHESS_GAME_JUDGEMENTS.to_owned()), self.variant())
+    }
+
+    pub fn group_id(&self) -> String {
+        self.group_id.as_ref().map(|v| v.to_owned()).unwrap_or_else(|| match self.variant() {
+            LichessVariant::Standard => "bigdata-chess-move-judgement".to_owned(),
+            other => format!("bigdata-chess-move-judgement-{}", other.name()),
+        })
+    }
+
+    // judgements are uploaded to object storage (with consumer offsets committed) after this many games
+    pub fn flush_games(&self) -> u64 {
+        self.flush_games.unwrap_or(100_000)
+    }
+
+    pub fn max_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.max_poll_interval_secs.unwrap_or(300))
+    }
+
+    // has to be stable across restarts, so that transactions of previous instance are fenced off.
+    // Each running instance needs its own one.
+    pub fn transactional_id(&self) -> String {
+        self.transactional_id.as_ref().map(|v| v.to_owned()).unwrap_or_else(|| match self.variant() {
+            LichessVariant::Standard => "bigdata-chess-move-judgement".to_owned(),
+            other => format!("bigdata-chess-move-judgement-{}", other.name()),
+        })
+    }
+
+    // how many times summaries are sent again after their transaction is aborted, before the step fails
+    pub fn send_retries(&self) -> u32 {
+        self.send_retries.unwrap_or(5)
+    }
+}
+
+impl Default for OpeningTreeStepConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            group_id: None,
+            database_path: None,
+            max_ply: None,
+            rating_band_width: None,
+            flush_games: None,
+            max_poll_interval_secs: None,
+        }
+    }
+}
+
+impl OpeningTreeStepConfig {
+    pub fn from_topic(&self) -> String {
+        TOPIC_CHESS_GAMES.to_owned()
+    }
+
+    pub fn group_id(&self) -> String {
+        self.group_id.as_ref().map(|v| v.to_owned()).unwrap_or("bigdata-chess-opening-tree".to_owned())
+    }
+
+    pub fn database_path(&self) -> String {
+        self.database_path.as_ref().map(|v| v.to_owned()).unwrap_or("./opening_tree".to_owned())
+    }
+
+    // moves after this ply are not added to the tree
+    pub fn max_ply(&self) -> u32 {
+        self.max_ply.unwrap_or(20)
+    }
+
+    // games are grouped by average rating of both players, rounded down to this
+    pub fn rating_band_width(&self) -> u32 {
+        self.rating_band_width.unwrap_or(200)
+    }
+
+    // stats are kept in memory and written to the database (with consumer offsets committed) after this many games
+    pub fn flush_games(&self) -> u64 {
+        self.flush_games.unwrap_or(10_000)
+    }
+
+    pub fn max_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.max_poll_interval_secs.unwrap_or(300))
+    }
+}
+
+impl Default for PositionIndexStepConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            variant: None,
+            group_id: None,
+            database_path: None,
+            flush_games: None,
+            max_poll_interval_secs: None,
+        }
+    }
+}
+
+impl PositionIndexStepConfig {
+    pub fn variant(&self) -> LichessVariant {
+        self.variant.unwrap_or(LichessVariant::Standard)
+    }
+
+    pub fn from_topic(&self) -> String {
+        topic_for_variant(TOPIC_CHESS_GAMES, self.variant())
+    }
+
+    pub fn group_id(&self) -> String {
+        self.group_id.as_ref().map(|v| v.to_owned()).unwrap_or_else(|| match self.variant() {
+            LichessVariant::Standard => "bigdata-chess-position-index".to_owned(),
+            other => format!("bigdata-chess-position-index-{}", other.name()),
+        })
+    }
+
+    pub fn database_path(&self) -> String {
+        self.database_path.as_ref().map(|v| v.to_owned()).unwrap_or_else(|| match self.variant() {
+            LichessVariant::Standard => "./position_index".to_owned(),
+            other => format!("./position_index_{}", other.name()),
+        })
+    }
+
+    // positions are written to the index and uploaded to object storage (with consumer offsets committed) after this
+    // many games
+    pub fn flush_games(&self) -> u64 {
+        self.flush_games.unwrap_or(50_000)
+    }
+
+    pub fn max_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.max_poll_interval_secs.unwrap_or(300))
+    }
+}
+
+impl Default for Glicko2RatingsStepConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            variant: None,
+            to_topic: None,
+            group_id: None,
+            database_path: None,
+            reorder_window_secs: None,
+            flush_games: None,
+            max_poll_interval_secs: None,
+            transactional_id: None,
+            send_retries: None,
         }
     }
 }
 
+impl Glicko2RatingsStepConfig {
+    pub fn variant(&self) -> LichessVariant {
+        self.variant.unwrap_or(LichessVariant::Standard)
+    }
+
+    pub fn from_topic(&self) -> String {
+        topic_for_variant(TOPIC_CHESS_GAMES, self.variant())
+    }
+
+    pub fn to_topic(&self) -> String {
+        topic_for_variant(&self.to_topic.as_ref().map(|v| v.to_owned()).unwrap_or(TOPIC_CHESS_GAME_RATINGS.to_owned()), self.variant())
+    }
+
+    pub fn group_id(&self) -> String {
+        self.group_id.as_ref().map(|v| v.to_owned()).unwrap_or_else(|| match self.variant() {
+            LichessVariant::Standard => "bigdata-chess-glicko2-ratings".to_owned(),
+            other => format!("bigdata-chess-glicko2-ratings-{}", other.name()),
+        })
+    }
+
+    pub fn database_path(&self) -> String {
+        self.database_path.as_ref().map(|v| v.to_owned()).unwrap_or_else(|| match self.variant() {
+            LichessVariant::Standard => "./glicko2_ratings".to_owned(),
+            other => format!("./glicko2_ratings_{}", other.name()),
+        })
+    }
+
+    // games from different partitions are not ordered by date, so they are held back for this long (in game time)
+    // to be sorted before ratings are updated
+    pub fn reorder_window(&self) -> Duration {
+        Duration::from_secs(self.reorder_window_secs.unwrap_or(60 * 60))
+    }
+
+    // ratings are uploaded to object storage (with consumer offsets committed) after this many games
+    pub fn flush_games(&self) -> u64 {
+        self.flush_games.unwrap_or(50_000)
+    }
+
+    pub fn max_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.max_poll_interval_secs.unwrap_or(300))
+    }
+
+    // has to be stable across restarts, so that transactions of previous instance are fenced off.
+    // Each running instance needs its own one.
+    pub fn transactional_id(&self) -> String {
+        self.transactional_id.as_ref().map(|v| v.to_owned()).unwrap_or_else(|| match self.variant() {
+            LichessVariant::Standard => "bigdata-chess-glicko2-ratings".to_owned(),
+            other => format!("bigdata-chess-glicko2-ratings-{}", other.name()),
+        })
+    }
+
+    // how many times ratings are sent again after their transaction is aborted, before the step fails
+    pub fn send_retries(&self) -> u32 {
+        self.send_retries.unwrap_or(5)
+    }
+}
+
 impl Default for HdfsImportStepConfig {
     fn default() -> Self {
         Self {
@@ -229,8 +798,11 @@ impl Default for StorageConfig {
         Self {
             endpoint: None,
             access_key: None,
+            access_key_file: None,
             secret_key: None,
+            secret_key_file: None,
             remote_api_key: None,
+            remote_api_key_file: None,
         }
     }
 }
@@ -239,28 +811,67 @@ impl Default for DatabaseConfig {
     fn default() -> Self {
         Self {
             connection_string: None,
+            connection_string_file: None,
         }
     }
 }
 
 impl Config {
-    pub fn load() -> Self {
-        read_to_string("./config.toml")
-            .or_else(|_| read_to_string("/config/config.toml"))
-            .map_err(|err| err.to_string())
-            .and_then(|v| toml::from_str(&v).map_err(|err| err.to_string()))
-            .unwrap_or_else(|err| {
-                warn!("failed to read config: {}", err);
-                Config::default()
-            })
+    pub fn load() -> Result<Self> {
+        Self::load_with_overrides(None, &[])
+    }
+
+    // config is layered: file, then BIGDATA_CHESS__ environment variables, then `key.path=value` overrides (from cli flags).
+    // Secrets can be mounted as files and referenced with `*_file` fields.
+    pub fn load_with_overrides(path: Option<&str>, overrides: &[String]) -> Result<Self> {
+        let mut value = read_config_file(path)?;
+
+        for (key, raw_value) in env::vars() {
+            if let Some(key) = key.strip_prefix(ENV_PREFIX) {
+                let key = key.to_lowercase().replace("__", ".");
+                set_value(&mut value, &key, parse_value(&raw_value))
+                    .with_context(|| format!("failed to apply environment variable {}{}", ENV_PREFIX, key.to_uppercase().replace('.', "__")))?;
+            }
+        }
+
+        for assignment in overrides {
+            let (key, raw_value) = assignment.split_once('=')
+                .ok_or_else(|| anyhow!("expected config override to look like key.path=value, got: {}", assignment))?;
+            set_value(&mut value, key.trim(), parse_value(raw_value.trim()))
+                .with_context(|| format!("failed to apply config override {}", assignment))?;
+        }
+
+        let mut config: Config = serde_path_to_error::deserialize(Value::Table(value))
+            .map_err(|err| anyhow!("invalid config value for `{}`: {}", err.path(), err.inner()))?;
+        config.load_secrets()?;
+
+        Ok(config)
     }
 
     pub fn infra(&self) -> InfraConfig {
         self.infra.as_ref().cloned().unwrap_or_default()
     }
+
+    fn load_secrets(&mut self) -> Result<()> {
+        if let Some(infra) = self.infra.as_mut() {
+            if let Some(storage) = infra.storage.as_mut() {
+                load_secret(&mut storage.access_key, &storage.access_key_file)?;
+                load_secret(&mut storage.secret_key, &storage.secret_key_file)?;
+                load_secret(&mut storage.remote_api_key, &storage.remote_api_key_file)?;
+            }
+
+            load_secret(&mut infra.database.connection_string, &infra.database.connection_string_file)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl StepsConfig {
+    pub fn file_downloader(&self) -> FileDownloaderStepConfig {
+        self.file_downloader.as_ref().cloned().unwrap_or_default()
+    }
+
     pub fn chunk_splitter(&self) -> ChunkSplitterStepConfig {
         self.chunk_splitter.as_ref().cloned().unwrap_or_default()
     }
@@ -307,3 +918,52 @@ impl DatabaseConfig {
         self.connection_string.as_ref()
     }
 }
+
+fn read_config_file(path: Option<&str>) -> Result<Table> {
+    let path = match path {
+        Some(path) => path.to_owned(),
+        None => match DEFAULT_CONFIG_PATHS.iter().find(|path| Path::new(path).exists()) {
+            Some(path) => path.to_string(),
+            None => {
+                info!("config file not found, using defaults and environment");
+                return Ok(Table::new());
+            },
+        },
+    };
+
+    let content = read_to_string(&path).with_context(|| format!("failed to read config file {}", path))?;
+    toml::from_str(&content).with_context(|| format!("failed to parse config file {}", path))
+}
+
+// values are parsed as toml, so that `true` or `42` get correct types. Anything which is not valid toml is used as a string.
+fn parse_value(raw_value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", raw_value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw_value.to_owned()))
+}
+
+fn set_value(table: &mut Table, key: &str, value: Value) -> Result<()> {
+    match key.split_once('.') {
+        Some((head, rest)) => {
+            let child = table.entry(head.to_owned()).or_insert_with(|| Value::Table(Table::new()));
+            match child {
+                Value::Table(child) => set_value(child, rest, value),
+                _ => Err(anyhow!("`{}` is not a table", head)),
+            }
+        },
+        None => {
+            table.insert(key.to_owned(), value);
+            Ok(())
+        },
+    }
+}
+
+fn load_secret(value: &mut Option<String>, file: &Option<String>) -> Result<()> {
+    if let Some(file) = file {
+        let secret = read_to_string(file).with_context(|| format!("failed to read secret file {}", file))?;
+        *value = Some(secret.trim().to_owned());
+    }
+
+    Ok(())
+}
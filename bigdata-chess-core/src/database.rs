@@ -7,6 +7,9 @@ use {
     },
 };
 
+const INSERT_GAME_MOVE: &str = "insert into chess_game_moves (id, game_id, from_file, from_rank, to_file, to_rank, ply, role, uci, san, fen_after) \
+    values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) on conflict do nothing";
+
 pub struct Database {
     connection_string: String,
     client: tokio_postgres::Client,
@@ -28,7 +31,7 @@ impl Database {
         });
 
         info!("preparing statements");
-        let statement_insert_game_move = client.prepare("insert into chess_game_moves (id, game_id, from_file, from_rank, to_file, to_rank) values ($1, $2, $3, $4, $5, $6) on conflict do nothing").await.unwrap();
+        let statement_insert_game_move = client.prepare(INSERT_GAME_MOVE).await.unwrap();
 
         info!("connected to database");
         Self {
@@ -48,7 +51,7 @@ impl Database {
             }
         });
 
-        let statement_insert_game_move = client.prepare("insert into chess_game_moves (id, game_id, from_file, from_rank, to_file, to_rank) values ($1, $2, $3, $4, $5, $6) on conflict do nothing").await.unwrap();
+        let statement_insert_game_move = client.prepare(INSERT_GAME_MOVE).await.unwrap();
 
         Self {
             connection_string: self.connection_string.clone(),
@@ -58,10 +61,17 @@ impl Database {
     }
 
     pub async fn save_game(&self, game: ChessGameEntity) {
-        self.client.query("insert into chess_games (id, opening, white_player_elo) values ($1, $2, $3) on conflict do nothing", &[
+        self.client.query("insert into chess_games (id, opening, white_player_elo, white_player_accuracy, black_player_accuracy, \
+            rated, speed, tournament_id, swiss_id) values ($1, $2, $3, $4, $5, $6, $7, $8, $9) on conflict do nothing", &[
             &game.id(),
             &game.opening(),
             &(game.white_player_elo() as i32),
+            &game.white_player_accuracy(),
+            &game.black_player_accuracy(),
+            &game.rated(),
+            &game.speed(),
+            &game.tournament_id(),
+            &game.swiss_id(),
         ]).await.unwrap();
     }
 
@@ -69,10 +79,15 @@ impl Database {
         self.client.query(&self.statement_insert_game_move, &[
             &format!("{}:{}", game_move.game_id(), game_move.move_id()),
             &game_move.game_id(),
-            &game_move.from_file().map(|v| v as i32),
-            &game_move.from_rank().map(|v| v as i32),
-            &game_move.to_file().map(|v| v as i32),
-            &game_move.to_rank().map(|v| v as i32)
+            &game_move.from_file().map(|v| v as i16),
+            &game_move.from_rank().map(|v| v as i16),
+            &game_move.to_file().map(|v| v as i16),
+            &game_move.to_rank().map(|v| v as i16),
+            &game_move.ply().map(|v| v as i32),
+            &game_move.role().map(|v| v as i16),
+            &game_move.uci(),
+            &game_move.san(),
+            &game_move.fen_after(),
         ]).await.unwrap();
     }
 }
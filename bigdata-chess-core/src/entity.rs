@@ -1,8 +1,16 @@
 use {
+    std::collections::BTreeMap,
     typed_builder::TypedBuilder,
     serde::Serialize,
     chrono::{NaiveDateTime, NaiveDate},
-    crate::data::{ChessGame, NormalSan},
+    crate::{
+        data::{ChessGame, San, BoardMove, Speed, GameRatings},
+        lichess::{LichessVariant, LichessSpeed},
+        accuracy::player_accuracy,
+        opening::classify_opening,
+        eval::{Eval, Side},
+        judgement::{Judgement, winning_chances_loss},
+    },
 };
 
 #[derive(TypedBuilder, Serialize)]
@@ -25,6 +33,22 @@ pub struct ChessGameEntity {
     timecontrol_duration: Option<u32>,
     timecontrol_increment: Option<u32>,
     termination: u32,
+    variant: String,
+    starting_fen: Option<String>,
+    annotator: Option<String>,
+    white_player_fide_id: Option<u32>,
+    black_player_fide_id: Option<u32>,
+    extra_headers: Option<String>, // json object
+    white_player_accuracy: Option<f64>, // from evaluations in comments, not set for games without them
+    black_player_accuracy: Option<f64>,
+    // classified from moves, eco and opening above are copied from headers
+    opening_family: Option<String>,
+    opening_variation: Option<String>,
+    opening_ply: Option<u32>,
+    rated: Option<bool>,
+    speed: Option<String>,
+    tournament_id: Option<String>,
+    swiss_id: Option<String>,
 
     // partition key should be last field
     day: String, // same as date, but YYYY-MM-DD, to be used for partitioning
@@ -43,6 +67,12 @@ pub struct ChessGameMoveEntity {
     promotion: Option<u8>,
     is_check: bool,
     is_checkmate: bool,
+    // from replaying the game, not set for games parsed before it was introduced
+    ply: Option<u32>,
+    role: Option<u8>,
+    uci: Option<String>,
+    san: Option<String>,
+    fen_after: Option<String>,
 }
 
 // in hive: cluster by game_id
@@ -50,7 +80,60 @@ pub struct ChessGameMoveEntity {
 pub struct ChessGameCommentEval {
     game_id: String,
     move_id: u32,
-    eval: f32,
+    eval: Option<f32>, // pawns, not set for mate scores
+    ply: u32,
+    centipawns: Option<i32>,
+    mate_in: Option<i32>, // positive when white mates
+    side_to_move: String, // white or black
+    win_probability: f64, // for white, in percents
+}
+
+// in hive: cluster by game_id
+#[derive(TypedBuilder, Serialize)]
+pub struct ChessGameClockEntity {
+    game_id: String,
+    ply: u32,
+    side: String, // white or black
+    clock_remaining: u32, // seconds
+    time_spent: Option<i64>, // seconds, including increment. Not known for games without timecontrol.
+}
+
+// in hive: cluster by game_id. Only moves with evaluations both before and after them are judged.
+#[derive(TypedBuilder, Serialize)]
+pub struct ChessGameMoveJudgementEntity {
+    game_id: String,
+    move_id: u32,
+    ply: u32,
+    side: String, // white or black
+    judgement: Option<String>, // inaccuracy, mistake or blunder, not set for good moves
+    win_probability_before: f64, // for the side which made the move, in percents
+    win_probability_after: f64,
+    winning_chances_loss: f64,
+}
+
+// in hive: cluster by position_hash, so that games reaching a position are in one bucket
+#[derive(TypedBuilder, Serialize)]
+pub struct ChessGamePositionEntity {
+    game_id: String,
+    ply: u32, // 0 for the starting position
+    position_hash: i64,
+}
+
+// in hive: cluster by player_name. One row for each player in a game.
+#[derive(TypedBuilder, Serialize)]
+pub struct ChessGameRatingEntity {
+    game_id: String,
+    date: Option<i64>,
+    speed: String,
+    side: String, // white or black
+    player_name: String,
+    rating_before: f64, // glicko-2
+    rating_after: f64,
+    deviation_before: f64,
+    deviation_after: f64,
+    volatility_after: f64,
+    lichess_rating: u32,
+    lichess_rating_diff: Option<i32>,
 }
 
 impl ChessGameEntity {
@@ -65,6 +148,54 @@ impl ChessGameEntity {
     pub fn white_player_elo(&self) -> u32 {
         self.white_player_elo
     }
+
+    pub fn white_player_accuracy(&self) -> Option<f64> {
+        self.white_player_accuracy
+    }
+
+    pub fn black_player_accuracy(&self) -> Option<f64> {
+        self.black_player_accuracy
+    }
+
+    pub fn rated(&self) -> Option<bool> {
+        self.rated
+    }
+
+    pub fn speed(&self) -> Option<&str> {
+        self.speed.as_deref()
+    }
+
+    pub fn tournament_id(&self) -> Option<&str> {
+        self.tournament_id.as_deref()
+    }
+
+    pub fn swiss_id(&self) -> Option<&str> {
+        self.swiss_id.as_deref()
+    }
+}
+
+impl ChessGamePositionEntity {
+    pub fn game_id(&self) -> &str {
+        &self.game_id
+    }
+
+    pub fn ply(&self) -> u32 {
+        self.ply
+    }
+
+    pub fn position_hash(&self) -> i64 {
+        self.position_hash
+    }
+}
+
+impl ChessGameMoveJudgementEntity {
+    pub fn side(&self) -> &str {
+        &self.side
+    }
+
+    pub fn judgement(&self) -> Option<&str> {
+        self.judgement.as_deref()
+    }
 }
 
 impl ChessGameMoveEntity {
@@ -91,9 +222,41 @@ impl ChessGameMoveEntity {
     pub fn to_rank(&self) -> Option<u8> {
         self.to_rank
     }
+
+    pub fn ply(&self) -> Option<u32> {
+        self.ply
+    }
+
+    pub fn role(&self) -> Option<u8> {
+        self.role
+    }
+
+    pub fn uci(&self) -> Option<&str> {
+        self.uci.as_deref()
+    }
+
+    pub fn san(&self) -> Option<&str> {
+        self.san.as_deref()
+    }
+
+    pub fn fen_after(&self) -> Option<&str> {
+        self.fen_after.as_deref()
+    }
 }
 
 pub fn into_chess_game_entity(id: String, game: ChessGame) -> ChessGameEntity {
+    let variant = LichessVariant::from(game.variant());
+    let extra_headers = if game.extra_headers.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_string(&game.extra_headers.iter().collect::<BTreeMap<_, _>>()).unwrap())
+    };
+
+    let white_player_accuracy = player_accuracy(&game, Side::White);
+    let black_player_accuracy = player_accuracy(&game, Side::Black);
+    let opening = classify_opening(&game);
+    let speed = game.speed.and_then(Speed::from_i32).map(|v| LichessSpeed::from(v).name().to_owned());
+
     ChessGameEntity::builder()
         .id(id)
         .event_name(game.event_name)
@@ -105,9 +268,11 @@ pub fn into_chess_game_entity(id: String, game: ChessGame) -> ChessGameEntity {
         }).unwrap_or("0000-00-00".to_owned()))
         .black_player_name(game.black_player.as_ref().unwrap().name.clone())
         .black_player_elo(game.black_player.as_ref().unwrap().elo)
+        .black_player_fide_id(game.black_player.as_ref().unwrap().fide_id)
         .black_player_title(game.black_player.unwrap().title.map(|v| title_name_from_id(v)))
         .white_player_name(game.white_player.as_ref().unwrap().name.clone())
         .white_player_elo(game.white_player.as_ref().unwrap().elo)
+        .white_player_fide_id(game.white_player.as_ref().unwrap().fide_id)
         .white_player_title(game.white_player.unwrap().title.map(title_name_from_id))
         .result(game.result as u8)
         .rating_outcome_for_white(game.rating_outcome_for_white)
@@ -117,32 +282,216 @@ pub fn into_chess_game_entity(id: String, game: ChessGame) -> ChessGameEntity {
         .timecontrol_duration(game.timecontrol.as_ref().map(|v| v.duration as u32))
         .timecontrol_increment(game.timecontrol.map(|v| v.increment as u32))
         .termination(game.termination as u32)
+        .variant(variant.name().to_owned())
+        .starting_fen(Some(game.starting_fen).filter(|v| !v.is_empty()))
+        .annotator(Some(game.annotator).filter(|v| !v.is_empty()))
+        .extra_headers(extra_headers)
+        .white_player_accuracy(white_player_accuracy)
+        .black_player_accuracy(black_player_accuracy)
+        .opening_family(opening.as_ref().map(|v| v.family.clone()))
+        .opening_variation(opening.as_ref().and_then(|v| v.variation.clone()))
+        .opening_ply(opening.map(|v| v.ply))
+        .rated(game.rated)
+        .speed(speed)
+        .tournament_id(Some(game.tournament_id).filter(|v| !v.is_empty()))
+        .swiss_id(Some(game.swiss_id).filter(|v| !v.is_empty()))
         .build()
 }
 
-pub fn into_chess_game_move_entity(game_id: &str, move_id: u32, san: &NormalSan, is_check: bool, is_checkmate: bool) -> ChessGameMoveEntity {
+// without board move, only squares written in san are known
+pub fn into_chess_game_move_entity(game_id: &str, move_id: u32, san: &San, board_move: Option<&BoardMove>) -> ChessGameMoveEntity {
+    let is_check = san.is_check.unwrap_or(false);
+    let is_checkmate = san.is_checkmate.unwrap_or(false);
+
+    if let Some(board_move) = board_move {
+        return ChessGameMoveEntity::builder()
+            .game_id(game_id.to_owned())
+            .move_id(move_id)
+            .from_file(board_move.from.as_ref().map(|v| v.file as u8))
+            .from_rank(board_move.from.as_ref().map(|v| v.rank as u8))
+            .to_file(board_move.to.as_ref().map(|v| v.file as u8))
+            .to_rank(board_move.to.as_ref().map(|v| v.rank as u8))
+            .capture(board_move.capture)
+            .promotion(board_move.promotion.map(|v| v as u8))
+            .is_check(is_check)
+            .is_checkmate(is_checkmate)
+            .ply(Some(board_move.ply))
+            .role(Some(board_move.role as u8))
+            .uci(Some(board_move.uci.clone()))
+            .san(Some(board_move.san.clone()))
+            .fen_after(Some(board_move.fen_after.clone()))
+            .build();
+    }
+
+    let normal = san.normal.as_ref();
     ChessGameMoveEntity::builder()
         .game_id(game_id.to_owned())
         .move_id(move_id)
-        .from_file(san.file.map(|v| v as u8))
-        .from_rank(san.rank.map(|v| v as u8))
-        .to_file(san.to.as_ref().map(|v| v.file as u8))
-        .to_rank(san.to.as_ref().map(|v| v.rank as u8))
-        .capture(san.capture)
-        .promotion(san.promotion.map(|v| v as u8))
+        .from_file(normal.and_then(|v| v.file).map(|v| v as u8))
+        .from_rank(normal.and_then(|v| v.rank).map(|v| v as u8))
+        .to_file(normal.and_then(|v| v.to.as_ref()).map(|v| v.file as u8))
+        .to_rank(normal.and_then(|v| v.to.as_ref()).map(|v| v.rank as u8))
+        .capture(normal.map(|v| v.capture).unwrap_or(false))
+        .promotion(normal.and_then(|v| v.promotion).map(|v| v as u8))
         .is_check(is_check)
         .is_checkmate(is_checkmate)
+        .ply(None)
+        .role(normal.map(|v| v.role as u8))
+        .uci(None)
+        .san(None)
+        .fen_after(None)
         .build()
 }
 
-pub fn into_chess_game_comment_eval_entity(game_id: &str, move_id: u32, eval: f32) -> ChessGameCommentEval {
+// ply is the number of moves made before the evaluated position
+pub fn into_chess_game_comment_eval_entity(game_id: &str, move_id: u32, ply: u32, eval: &Eval) -> ChessGameCommentEval {
     ChessGameCommentEval::builder()
         .game_id(game_id.to_owned())
         .move_id(move_id)
-        .eval(eval)
+        .eval(eval.centipawns().map(|v| v as f32 / 100.0))
+        .ply(ply)
+        .centipawns(eval.centipawns())
+        .mate_in(eval.mate_in())
+        .side_to_move(eval.side_to_move.name().to_owned())
+        .win_probability(eval.win_probability())
         .build()
 }
 
+// clock comment follows the move it was recorded after
+pub fn into_chess_game_clock_entities(game_id: &str, game: &ChessGame) -> Vec<ChessGameClockEntity> {
+    let first_to_move = Side::first_to_move(&game.starting_fen);
+    let initial_clock = game.timecontrol.as_ref().map(|v| v.duration as i64);
+    let increment = game.timecontrol.as_ref().map(|v| v.increment as i64).unwrap_or(0);
+
+    let mut clocks = Vec::new();
+    let mut ply = 0;
+    // previous clock of white and black
+    let mut previous_clock = [initial_clock, initial_clock];
+
+    for entry in &game.game_entries {
+        if entry.san.is_some() {
+            ply += 1;
+            continue;
+        }
+
+        let clock = match entry.comment.as_ref().and_then(|v| v.clock) {
+            Some(v) => v,
+            None => continue,
+        };
+        if ply == 0 {
+            continue;
+        }
+
+        // the side which made the last move
+        let side = Side::to_move_after(ply - 1, first_to_move);
+        let is_white = side == Side::White;
+        let side = if is_white { 0 } else { 1 };
+        let time_spent = previous_clock[side].map(|previous| previous - clock as i64 + increment);
+        previous_clock[side] = Some(clock as i64);
+
+        clocks.push(ChessGameClockEntity::builder()
+            .game_id(game_id.to_owned())
+            .ply(ply)
+            .side(if is_white { "white" } else { "black" }.to_owned())
+            .clock_remaining(clock)
+            .time_spent(time_spent)
+            .build());
+    }
+
+    clocks
+}
+
+// evaluation of the position after the move is in the comment which follows it
+pub fn into_chess_game_move_judgement_entities(game_id: &str, game: &ChessGame) -> Vec<ChessGameMoveJudgementEntity> {
+    let first_to_move = Side::first_to_move(&game.starting_fen);
+
+    let mut judgements = Vec::new();
+    let mut entry_index = 0;
+    let mut ply = 0;
+    // move id of the last move and evaluation before it
+    let mut last_move: Option<(u32, Option<Eval>)> = None;
+    let mut last_eval: Option<Eval> = None;
+
+    for entry in &game.game_entries {
+        entry_index += 1;
+
+        if entry.san.is_some() {
+            ply += 1;
+            last_move = Some((entry_index, last_eval.take()));
+            continue;
+        }
+
+        let eval = match entry.comment.as_ref().and_then(|v| Eval::from_comment(v, Side::to_move_after(ply, first_to_move))) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        if let Some((move_id, Some(before))) = last_move.take() {
+            let mover = before.side_to_move;
+            judgements.push(ChessGameMoveJudgementEntity::builder()
+                .game_id(game_id.to_owned())
+                .move_id(move_id)
+                .ply(ply)
+                .side(mover.name().to_owned())
+                .judgement(Judgement::from_evals(&before, &eval, mover).map(|v| v.name().to_owned()))
+                .win_probability_before(before.win_probability_for(mover))
+                .win_probability_after(eval.win_probability_for(mover))
+                .winning_chances_loss(winning_chances_loss(&before, &eval, mover))
+                .build());
+        }
+
+        last_eval = Some(eval);
+    }
+
+    judgements
+}
+
+// games parsed before position hashes were introduced do not have them
+pub fn into_chess_game_position_entities(game_id: &str, game: &ChessGame) -> Vec<ChessGamePositionEntity> {
+    let mut positions = Vec::new();
+
+    if game.starting_position_hash != 0 {
+        positions.push((0, game.starting_position_hash));
+    }
+    for board_move in game.game_entries.iter().filter_map(|v| v.board_move.as_ref()) {
+        if board_move.position_hash != 0 {
+            positions.push((board_move.ply, board_move.position_hash));
+        }
+    }
+
+    positions.into_iter()
+        .map(|(ply, position_hash)| ChessGamePositionEntity::builder()
+            .game_id(game_id.to_owned())
+            .ply(ply)
+            .position_hash(position_hash)
+            .build())
+        .collect()
+}
+
+pub fn into_chess_game_rating_entities(ratings: &GameRatings) -> Vec<ChessGameRatingEntity> {
+    let speed = LichessSpeed::from(ratings.speed()).name();
+    let date = ratings.date.as_ref().map(|v| v.seconds);
+
+    [(Side::White, ratings.white.as_ref()), (Side::Black, ratings.black.as_ref())]
+        .into_iter()
+        .filter_map(|(side, change)| change.map(|change| (side, change)))
+        .map(|(side, change)| ChessGameRatingEntity::builder()
+            .game_id(ratings.game_id.clone())
+            .date(date)
+            .speed(speed.to_owned())
+            .side(side.name().to_owned())
+            .player_name(change.name.clone())
+            .rating_before(change.rating_before)
+            .rating_after(change.rating_after)
+            .deviation_before(change.deviation_before)
+            .deviation_after(change.deviation_after)
+            .volatility_after(change.volatility_after)
+            .lichess_rating(change.lichess_rating)
+            .lichess_rating_diff(change.lichess_rating_diff)
+            .build())
+        .collect()
+}
+
 fn title_name_from_id(id: i32) -> String {
     match id {
         0 => "FM",
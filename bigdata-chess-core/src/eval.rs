@@ -0,0 +1,110 @@
+use crate::data::Comment;
+
+// lichess caps evaluations at 10 pawns when converting them into winning chances
+const CENTIPAWNS_CEILING: i32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    White,
+    Black,
+}
+
+// from white's perspective, like in lichess %eval comments
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Score {
+    Centipawns(i32),
+    Mate(i32), // positive when white mates in N moves, negative when black does
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Eval {
+    pub score: Score,
+    pub side_to_move: Side,
+}
+
+impl Side {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::White => "white",
+            Self::Black => "black",
+        }
+    }
+
+    pub fn opposite(&self) -> Self {
+        match self {
+            Self::White => Self::Black,
+            Self::Black => Self::White,
+        }
+    }
+
+    // games from custom positions may start with black to move
+    pub fn first_to_move(starting_fen: &str) -> Self {
+        if starting_fen.split(' ').nth(1) == Some("b") {
+            Self::Black
+        } else {
+            Self::White
+        }
+    }
+
+    pub fn to_move_after(ply: u32, first_to_move: Side) -> Self {
+        if ply % 2 == 0 {
+            first_to_move
+        } else {
+            first_to_move.opposite()
+        }
+    }
+}
+
+impl Eval {
+    pub fn from_comment(comment: &Comment, side_to_move: Side) -> Option<Self> {
+        let score = if let Some(mate) = comment.getting_mated_in {
+            Score::Mate(mate)
+        } else {
+            Score::Centipawns((comment.eval? * 100.0).round() as i32)
+        };
+
+        Some(Self {
+            score,
+            side_to_move,
+        })
+    }
+
+    pub fn centipawns(&self) -> Option<i32> {
+        match self.score {
+            Score::Centipawns(v) => Some(v),
+            Score::Mate(_) => None,
+        }
+    }
+
+    pub fn mate_in(&self) -> Option<i32> {
+        match self.score {
+            Score::Centipawns(_) => None,
+            Score::Mate(v) => Some(v),
+        }
+    }
+
+    // mate counts as the maximum advantage
+    pub fn centipawns_ceiled(&self) -> i32 {
+        match self.score {
+            Score::Centipawns(v) => v.clamp(-CENTIPAWNS_CEILING, CENTIPAWNS_CEILING),
+            Score::Mate(v) => CENTIPAWNS_CEILING * v.signum(),
+        }
+    }
+
+    // from -1 (black wins) to 1 (white wins)
+    pub fn winning_chances(&self) -> f64 {
+        2.0 / (1.0 + (-0.00368208 * self.centipawns_ceiled() as f64).exp()) - 1.0
+    }
+
+    // chance of white to win in percents, same as in lichess
+    pub fn win_probability(&self) -> f64 {
+        50.0 + 50.0 * self.winning_chances()
+    }
+
+    pub fn win_probability_for(&self, side: Side) -> f64 {
+        match side {
+            Side::White => self.win_probability(),
+            Side::Black => 100.0 - self.win_probability(),
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use {
+    sha2::{Sha256, Digest},
+    url::Url,
+};
+
+// lichess game ids are 8 alphanumeric characters, links to the game from the side of one of the players have 4 more
+const LICHESS_GAME_ID_LENGTH: usize = 8;
+
+// something like https://lichess.org/abcdEFGH
+pub fn game_id_from_link(link: &str) -> Option<String> {
+    let url = Url::parse(link).ok()?;
+    if url.host_str()? != "lichess.org" {
+        return None;
+    }
+
+    let id = url.path_segments()?.next()?;
+    if id.len() < LICHESS_GAME_ID_LENGTH || !id.chars().all(|v| v.is_ascii_alphanumeric()) {
+        return None;
+    }
+
+    Some(id[0..LICHESS_GAME_ID_LENGTH].to_owned())
+}
+
+// for games without lichess link. Longer than lichess ids, so that they never collide.
+pub fn game_id_from_content(pgn: &str) -> String {
+    let digest = Sha256::digest(pgn.as_bytes());
+    digest[0..16].iter().map(|v| format!("{:02x}", v)).collect()
+}
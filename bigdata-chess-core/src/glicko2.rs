@@ -0,0 +1,113 @@
+use std::f64::consts::PI;
+
+// parameters are the same as in lichess
+const DEFAULT_RATING: f64 = 1500.0;
+const DEFAULT_DEVIATION: f64 = 500.0;
+const DEFAULT_VOLATILITY: f64 = 0.09;
+const MIN_DEVIATION: f64 = 45.0;
+const MAX_DEVIATION: f64 = 500.0;
+const TAU: f64 = 0.75;
+const RATING_PERIODS_PER_DAY: f64 = 0.21436;
+
+const SCALE: f64 = 173.7178;
+const CONVERGENCE_TOLERANCE: f64 = 0.000001;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glicko2Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Glicko2Rating {
+    fn default() -> Self {
+        Self {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+
+impl Glicko2Rating {
+    // deviation grows while player does not play
+    pub fn after_inactivity(&self, days: f64) -> Self {
+        let phi = self.deviation / SCALE;
+        let periods = days.max(0.0) * RATING_PERIODS_PER_DAY;
+        let phi = (phi * phi + periods * self.volatility * self.volatility).sqrt();
+
+        Self {
+            deviation: (phi * SCALE).clamp(MIN_DEVIATION, MAX_DEVIATION),
+            ..*self
+        }
+    }
+
+    // rating after a single game, like in lichess where every game is a rating period of its own. Score is 1 for
+    // win, 0.5 for draw and 0 for loss.
+    pub fn after_game(&self, opponent: &Glicko2Rating, score: f64) -> Self {
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+        let opponent_mu = (opponent.rating - DEFAULT_RATING) / SCALE;
+        let opponent_phi = opponent.deviation / SCALE;
+
+        let opponent_g = g(opponent_phi);
+        let expected = 1.0 / (1.0 + (-opponent_g * (mu - opponent_mu)).exp());
+        let variance = 1.0 / (opponent_g * opponent_g * expected * (1.0 - expected));
+        let delta = variance * opponent_g * (score - expected);
+
+        let volatility = new_volatility(phi, self.volatility, variance, delta);
+
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / variance).sqrt();
+        let new_mu = mu + new_phi * new_phi * opponent_g * (score - expected);
+
+        Self {
+            rating: new_mu * SCALE + DEFAULT_RATING,
+            deviation: (new_phi * SCALE).clamp(MIN_DEVIATION, MAX_DEVIATION),
+            volatility,
+        }
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+// step 5 of glicko-2, using the illinois algorithm
+fn new_volatility(phi: f64, volatility: f64, variance: f64, delta: f64) -> f64 {
+    let a = (volatility * volatility).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        ex * (delta * delta - phi * phi - variance - ex) / (2.0 * (phi * phi + variance + ex).powi(2)) - (x - a) / (TAU * TAU)
+    };
+
+    let mut big_a = a;
+    let mut big_b = if delta * delta > phi * phi + variance {
+        (delta * delta - phi * phi - variance).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+
+    let mut f_a = f(big_a);
+    let mut f_b = f(big_b);
+    while (big_b - big_a).abs() > CONVERGENCE_TOLERANCE {
+        let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+        let f_c = f(big_c);
+
+        if f_c * f_b <= 0.0 {
+            big_a = big_b;
+            f_a = f_b;
+        } else {
+            f_a /= 2.0;
+        }
+
+        big_b = big_c;
+        f_b = f_c;
+    }
+
+    (big_a / 2.0).exp()
+}
@@ -0,0 +1,46 @@
+use crate::eval::{Eval, Side};
+
+// thresholds on drop of winning chances (from -1 to 1) of the side which made the move, same as in lichess
+const INACCURACY_THRESHOLD: f64 = 0.1;
+const MISTAKE_THRESHOLD: f64 = 0.2;
+const BLUNDER_THRESHOLD: f64 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    // before is the evaluation of the position in which the move was made, after is the one of the resulting position
+    pub fn from_evals(before: &Eval, after: &Eval, mover: Side) -> Option<Self> {
+        let loss = winning_chances_loss(before, after, mover);
+
+        if loss >= BLUNDER_THRESHOLD {
+            Some(Self::Blunder)
+        } else if loss >= MISTAKE_THRESHOLD {
+            Some(Self::Mistake)
+        } else if loss >= INACCURACY_THRESHOLD {
+            Some(Self::Inaccuracy)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Inaccuracy => "inaccuracy",
+            Self::Mistake => "mistake",
+            Self::Blunder => "blunder",
+        }
+    }
+}
+
+pub fn winning_chances_loss(before: &Eval, after: &Eval, mover: Side) -> f64 {
+    let loss = before.winning_chances() - after.winning_chances();
+    match mover {
+        Side::White => loss,
+        Side::Black => -loss,
+    }
+}
@@ -1,8 +1,15 @@
+pub mod accuracy;
 pub mod config;
 pub mod database;
 pub mod entity;
+pub mod eval;
+pub mod game_id;
+pub mod glicko2;
+pub mod judgement;
 pub mod lichess;
+pub mod opening;
 pub mod pgn;
+pub mod position;
 pub mod queue;
 pub mod storage;
 
@@ -1,21 +1,397 @@
+use {
+    std::{fmt, collections::HashMap},
+    serde::{Serialize, Deserialize},
+    anyhow::{anyhow, Result},
+    reqwest::header::{CONTENT_LENGTH, ETAG},
+    crate::data::{Variant, Speed, Timecontrol},
+};
+
 pub struct Lichess {
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub enum LichessVariant {
+    Standard,
+    Chess960,
+    Crazyhouse,
+    Antichess,
+    Atomic,
+    Horde,
+    KingOfTheHill,
+    RacingKings,
+    ThreeCheck,
+}
+
+// time control class, same as in lichess
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub enum LichessSpeed {
+    UltraBullet,
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+}
+
+// Event header of lichess games, like "Rated Blitz tournament https://lichess.org/tournament/xyz"
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LichessEvent {
+    pub rated: Option<bool>,
+    pub speed: Option<LichessSpeed>,
+    pub tournament_id: Option<String>,
+    pub swiss_id: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String")]
+pub struct YearMonth {
+    pub year: u32,
+    pub month: u32,
+}
+
+// something like https://database.lichess.org/standard/lichess_db_standard_rated_2013-01.pgn.zst
+// or /standard/lichess_db_standard_rated_2013-01 when in object storage
+#[derive(Clone, Debug)]
+pub struct LichessDataFile {
+    pub url: String,
+    pub variant: LichessVariant,
+    pub date: YearMonth,
+}
+
+// used to detect when lichess re-uploads a dump
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LichessDataFileInfo {
+    pub size: u64,
+    pub etag: Option<String>,
 }
 
 impl Lichess {
     pub fn new() -> Self {
         Self {
+            client: reqwest::Client::new(),
         }
     }
 
-    pub async fn files_from_lichess_database_list(&self) -> Vec<String> {
-        reqwest::get("https://database.lichess.org/standard/list.txt")
+    pub async fn files_from_lichess_database_list(&self, variant: LichessVariant) -> Vec<String> {
+        self.client.get(format!("https://database.lichess.org/{}/list.txt", variant.name()))
+            .send()
             .await
             .unwrap()
             .text()
             .await
             .unwrap()
             .split("\n")
+            .filter(|v| !v.is_empty())
             .map(|v| v.to_owned())
             .collect()
     }
-}
\ No newline at end of file
+
+    // file name -> hex encoded sha256 digest
+    pub async fn sha256_sums(&self, variant: LichessVariant) -> Result<HashMap<String, String>> {
+        let res = self.client.get(format!("https://database.lichess.org/{}/sha256sums.txt", variant.name()))
+            .send()
+            .await
+            .map_err(|err| anyhow!("failed to fetch sha256 sums: {:?}", err))?
+            .text()
+            .await
+            .map_err(|err| anyhow!("failed to read sha256 sums: {:?}", err))?;
+
+        Ok(res.lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(digest, file_name)| (file_name.trim().trim_start_matches('*').to_owned(), digest.to_lowercase()))
+            .collect())
+    }
+
+    pub async fn file_info(&self, url: &str) -> Result<LichessDataFileInfo> {
+        let res = self.client.head(url).send().await
+            .map_err(|err| anyhow!("failed to send HEAD request for {}: {:?}", url, err))?;
+
+        let size = res.headers().get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| anyhow!("no content length for {}", url))?;
+        let etag = res.headers().get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_owned());
+
+        Ok(LichessDataFileInfo {
+            size,
+            etag,
+        })
+    }
+}
+
+impl LichessVariant {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Standard => "standard",
+            Self::Chess960 => "chess960",
+            Self::Crazyhouse => "crazyhouse",
+            Self::Antichess => "antichess",
+            Self::Atomic => "atomic",
+            Self::Horde => "horde",
+            Self::KingOfTheHill => "kingOfTheHill",
+            Self::RacingKings => "racingKings",
+            Self::ThreeCheck => "threeCheck",
+        }
+    }
+
+    pub fn is_standard(&self) -> bool {
+        *self == Self::Standard
+    }
+}
+
+impl TryFrom<&str> for LichessVariant {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(match value {
+            "standard" => Self::Standard,
+            "chess960" => Self::Chess960,
+            "crazyhouse" => Self::Crazyhouse,
+            "antichess" => Self::Antichess,
+            "atomic" => Self::Atomic,
+            "horde" => Self::Horde,
+            "kingOfTheHill" => Self::KingOfTheHill,
+            "racingKings" => Self::RacingKings,
+            "threeCheck" => Self::ThreeCheck,
+            other => return Err(format!("Unexpected lichess variant: {}", other)),
+        })
+    }
+}
+
+impl TryFrom<String> for LichessVariant {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl LichessSpeed {
+    // lichess estimates game duration as if it lasted for 40 moves
+    pub fn from_timecontrol(duration: u32, increment: u32) -> Self {
+        let estimated_duration = duration + 40 * increment;
+
+        if estimated_duration < 30 {
+            Self::UltraBullet
+        } else if estimated_duration < 180 {
+            Self::Bullet
+        } else if estimated_duration < 480 {
+            Self::Blitz
+        } else if estimated_duration < 1500 {
+            Self::Rapid
+        } else if estimated_duration < 21600 {
+            Self::Classical
+        } else {
+            Self::Correspondence
+        }
+    }
+
+    // games without clock are correspondence
+    pub fn from_game_timecontrol(timecontrol: Option<&Timecontrol>) -> Self {
+        timecontrol
+            .map(|v| Self::from_timecontrol(v.duration as u32, v.increment as u32))
+            .unwrap_or(Self::Correspondence)
+    }
+
+    // speed in event name is capitalized, like "UltraBullet"
+    fn from_event_name(name: &str) -> Option<Self> {
+        Some(match name.to_lowercase().as_str() {
+            "ultrabullet" => Self::UltraBullet,
+            "bullet" => Self::Bullet,
+            "blitz" => Self::Blitz,
+            "rapid" => Self::Rapid,
+            "classical" => Self::Classical,
+            "correspondence" => Self::Correspondence,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UltraBullet => "ultraBullet",
+            Self::Bullet => "bullet",
+            Self::Blitz => "blitz",
+            Self::Rapid => "rapid",
+            Self::Classical => "classical",
+            Self::Correspondence => "correspondence",
+        }
+    }
+}
+
+impl LichessEvent {
+    // events of games from elsewhere have nothing recognized
+    pub fn parse(event: &str) -> Self {
+        let mut tokens = event.split_whitespace();
+
+        let rated = match tokens.next() {
+            Some("Rated") => Some(true),
+            Some("Casual") => Some(false),
+            _ => None,
+        };
+        let speed = if rated.is_some() {
+            tokens.next().and_then(LichessSpeed::from_event_name)
+        } else {
+            None
+        };
+
+        Self {
+            rated,
+            speed,
+            tournament_id: id_from_event_link(event, "lichess.org/tournament/"),
+            swiss_id: id_from_event_link(event, "lichess.org/swiss/"),
+        }
+    }
+}
+
+fn id_from_event_link(event: &str, prefix: &str) -> Option<String> {
+    event.split_whitespace()
+        .filter_map(|v| v.split_once(prefix))
+        .map(|(_, id)| id.split('/').next().unwrap_or(id))
+        .find(|v| !v.is_empty())
+        .map(|v| v.to_owned())
+}
+
+impl TryFrom<&str> for LichessSpeed {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(match value {
+            "ultraBullet" => Self::UltraBullet,
+            "bullet" => Self::Bullet,
+            "blitz" => Self::Blitz,
+            "rapid" => Self::Rapid,
+            "classical" => Self::Classical,
+            "correspondence" => Self::Correspondence,
+            other => return Err(format!("Unexpected lichess speed: {}", other)),
+        })
+    }
+}
+
+impl TryFrom<String> for LichessSpeed {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl From<LichessSpeed> for Speed {
+    fn from(value: LichessSpeed) -> Self {
+        match value {
+            LichessSpeed::UltraBullet => Self::UltraBullet,
+            LichessSpeed::Bullet => Self::Bullet,
+            LichessSpeed::Blitz => Self::Blitz,
+            LichessSpeed::Rapid => Self::Rapid,
+            LichessSpeed::Classical => Self::Classical,
+            LichessSpeed::Correspondence => Self::Correspondence,
+        }
+    }
+}
+
+impl From<Speed> for LichessSpeed {
+    fn from(value: Speed) -> Self {
+        match value {
+            Speed::UltraBullet => Self::UltraBullet,
+            Speed::Bullet => Self::Bullet,
+            Speed::Blitz => Self::Blitz,
+            Speed::Rapid => Self::Rapid,
+            Speed::Classical => Self::Classical,
+            Speed::Correspondence => Self::Correspondence,
+        }
+    }
+}
+
+impl From<LichessVariant> for Variant {
+    fn from(value: LichessVariant) -> Self {
+        match value {
+            LichessVariant::Standard => Self::Standard,
+            LichessVariant::Chess960 => Self::Chess960,
+            LichessVariant::Crazyhouse => Self::Crazyhouse,
+            LichessVariant::Antichess => Self::Antichess,
+            LichessVariant::Atomic => Self::Atomic,
+            LichessVariant::Horde => Self::Horde,
+            LichessVariant::KingOfTheHill => Self::KingOfTheHill,
+            LichessVariant::RacingKings => Self::RacingKings,
+            LichessVariant::ThreeCheck => Self::ThreeCheck,
+        }
+    }
+}
+
+impl From<Variant> for LichessVariant {
+    fn from(value: Variant) -> Self {
+        match value {
+            Variant::Standard => Self::Standard,
+            Variant::Chess960 => Self::Chess960,
+            Variant::Crazyhouse => Self::Crazyhouse,
+            Variant::Antichess => Self::Antichess,
+            Variant::Atomic => Self::Atomic,
+            Variant::Horde => Self::Horde,
+            Variant::KingOfTheHill => Self::KingOfTheHill,
+            Variant::RacingKings => Self::RacingKings,
+            Variant::ThreeCheck => Self::ThreeCheck,
+        }
+    }
+}
+
+impl TryFrom<&str> for YearMonth {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let (year, month) = value.split_once('-')
+            .ok_or_else(|| format!("Expected month to look like YYYY-MM: {}", value))?;
+        let year = year.parse().map_err(|_| format!("Unexpected year in {}", value))?;
+        let month = month.parse().map_err(|_| format!("Unexpected month in {}", value))?;
+        if !(1..=12).contains(&month) {
+            return Err(format!("Unexpected month in {}", value));
+        }
+
+        Ok(Self {
+            year,
+            month,
+        })
+    }
+}
+
+impl TryFrom<String> for YearMonth {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl fmt::Display for YearMonth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+impl LichessDataFile {
+    pub fn from_url(url: &str) -> Result<Self, String> {
+        let file_name = file_name_from_url(url);
+        let name = file_name.strip_prefix("lichess_db_")
+            .map(|v| v.strip_suffix(".pgn.zst").unwrap_or(v))
+            .ok_or_else(|| format!("Unexpected lichess data file name: {}", file_name))?;
+        let (variant, date) = name.split_once("_rated_")
+            .ok_or_else(|| format!("Unexpected lichess data file name: {}", file_name))?;
+
+        Ok(Self {
+            url: url.to_owned(),
+            variant: LichessVariant::try_from(variant)?,
+            date: YearMonth::try_from(date)?,
+        })
+    }
+
+    pub fn file_name(&self) -> &str {
+        file_name_from_url(&self.url)
+    }
+}
+
+fn file_name_from_url(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
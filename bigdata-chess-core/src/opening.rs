@@ -0,0 +1,117 @@
+use {
+    std::collections::HashMap,
+    once_cell::sync::Lazy,
+    tracing::warn,
+    shakmaty::{
+        fen::Fen,
+        san::SanPlus,
+        Chess,
+        EnPassantMode,
+        Position,
+    },
+    crate::data::ChessGame,
+};
+
+// lines of named openings in the format of https://github.com/lichess-org/chess-openings (one file per eco volume): eco,
+// name and pgn. only main lines are bundled, see data/openings/README.md
+const OPENINGS_TSV: [&str; 5] = [
+    include_str!("../data/openings/a.tsv"),
+    include_str!("../data/openings/b.tsv"),
+    include_str!("../data/openings/c.tsv"),
+    include_str!("../data/openings/d.tsv"),
+    include_str!("../data/openings/e.tsv"),
+];
+
+static OPENINGS: Lazy<Openings> = Lazy::new(|| Openings::from_tsv(&OPENINGS_TSV));
+
+#[derive(Clone, Debug)]
+pub struct Opening {
+    pub eco: String,
+    pub family: String, // like "Sicilian Defense"
+    pub variation: Option<String>, // like "Najdorf Variation", or "Two Knights Defense, Fried Liver Attack"
+    pub ply: u32, // number of moves in the opening line
+}
+
+// openings are matched by position, so that transpositions into a named opening are classified too
+struct Openings {
+    by_position: HashMap<String, Opening>,
+    max_ply: u32,
+}
+
+impl Openings {
+    fn from_tsv(files: &[&str]) -> Self {
+        let mut by_position = HashMap::new();
+        let mut max_ply = 0;
+
+        for line in files.iter().flat_map(|tsv| tsv.lines().skip(1)) {
+            let mut fields = line.split('\t');
+            let (eco, name, pgn) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(eco), Some(name), Some(pgn)) => (eco, name, pgn),
+                _ => continue,
+            };
+
+            let (position, ply) = match replay_pgn(pgn) {
+                Ok(v) => v,
+                Err(err) => {
+                    warn!("skipping opening {} which cannot be replayed: {}", name, err);
+                    continue;
+                },
+            };
+
+            let (family, variation) = match name.split_once(": ") {
+                Some((family, variation)) => (family.to_owned(), Some(variation.to_owned())),
+                None => (name.to_owned(), None),
+            };
+
+            max_ply = max_ply.max(ply);
+            by_position.insert(position_key(&position), Opening {
+                eco: eco.to_owned(),
+                family,
+                variation,
+                ply,
+            });
+        }
+
+        Self {
+            by_position,
+            max_ply,
+        }
+    }
+}
+
+// the deepest named opening reached in the game. Only games which were replayed while parsing can be classified.
+pub fn classify_opening(game: &ChessGame) -> Option<Opening> {
+    game.game_entries.iter()
+        .filter_map(|v| v.board_move.as_ref())
+        .take_while(|v| v.ply <= OPENINGS.max_ply)
+        .filter_map(|v| OPENINGS.by_position.get(fen_position_key(&v.fen_after)))
+        .last()
+        .cloned()
+}
+
+fn replay_pgn(pgn: &str) -> Result<(Chess, u32), String> {
+    let mut position = Chess::default();
+    let mut ply = 0;
+
+    // move numbers like "1." are skipped
+    for token in pgn.split_whitespace().filter(|v| !v.ends_with('.')) {
+        let san: SanPlus = token.parse().map_err(|err| format!("failed to parse {}: {}", token, err))?;
+        let m = san.san.to_move(&position).map_err(|err| format!("illegal move {}: {}", token, err))?;
+        position.play_unchecked(&m);
+        ply += 1;
+    }
+
+    Ok((position, ply))
+}
+
+fn position_key(position: &Chess) -> String {
+    fen_position_key(&Fen::from_position(position.clone(), EnPassantMode::Legal).to_string()).to_owned()
+}
+
+// fen without move counters
+fn fen_position_key(fen: &str) -> &str {
+    match fen.match_indices(' ').nth(3) {
+        Some((index, _)) => &fen[..index],
+        None => fen,
+    }
+}
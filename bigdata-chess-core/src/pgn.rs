@@ -7,6 +7,7 @@ use {
         CastlingSide,
         Nag,
         PlayerTitle,
+        Variant,
     }
 };
 
@@ -71,17 +72,20 @@ impl From<pgn_reader::CastlingSide> for CastlingSide {
     }
 }
 
-impl From<pgn_reader::Nag> for Nag {
-    fn from(value: pgn_reader::Nag) -> Self {
-        match value {
+// other nags are not used by lichess
+impl TryFrom<pgn_reader::Nag> for Nag {
+    type Error = u8;
+
+    fn try_from(value: pgn_reader::Nag) -> Result<Self, Self::Error> {
+        Ok(match value {
             pgn_reader::Nag::GOOD_MOVE => Self::GoodMove,
-            pgn_reader::Nag ::MISTAKE => Self::Mistake,
+            pgn_reader::Nag::MISTAKE => Self::Mistake,
             pgn_reader::Nag::BRILLIANT_MOVE => Self::BrilliantMove,
             pgn_reader::Nag::BLUNDER => Self::Blunder,
             pgn_reader::Nag::SPECULATIVE_MOVE => Self::SpeculativeMove,
             pgn_reader::Nag::DUBIOUS_MOVE => Self::DubiousMove,
-            other => panic!("Unexpected nag: {}", other),
-        }
+            other => return Err(other.0),
+        })
     }
 }
 
@@ -104,4 +108,24 @@ impl TryFrom<&str> for PlayerTitle {
             other => return Err(format!("Unexpected player title: {}", other)),
         })
     }
-}
\ No newline at end of file
+}
+
+// values of lichess Variant header
+impl TryFrom<&str> for Variant {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(match value {
+            "Standard" | "From Position" => Self::Standard,
+            "Chess960" => Self::Chess960,
+            "Crazyhouse" => Self::Crazyhouse,
+            "Antichess" => Self::Antichess,
+            "Atomic" => Self::Atomic,
+            "Horde" => Self::Horde,
+            "King of the Hill" => Self::KingOfTheHill,
+            "Racing Kings" => Self::RacingKings,
+            "Three-check" => Self::ThreeCheck,
+            other => return Err(format!("Unexpected variant: {}", other)),
+        })
+    }
+}
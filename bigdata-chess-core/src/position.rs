@@ -0,0 +1,20 @@
+use shakmaty::{
+    zobrist::{ZobristHash, Zobrist64},
+    fen::Fen,
+    CastlingMode,
+    Chess,
+    EnPassantMode,
+};
+
+// zobrist hash of the position. It is the same for transpositions, but unlike fen does not depend on move counters.
+// Stored as signed, so that it fits into bigint columns.
+pub fn position_hash<P: ZobristHash>(position: &P) -> i64 {
+    let hash: Zobrist64 = position.zobrist_hash(EnPassantMode::Legal);
+    hash.0 as i64
+}
+
+pub fn position_hash_from_fen(fen: &str) -> Result<i64, String> {
+    let fen: Fen = fen.parse().map_err(|err| format!("failed to parse fen: {}", err))?;
+    let position: Chess = fen.into_position(CastlingMode::Standard).map_err(|err| format!("invalid position: {}", err))?;
+    Ok(position_hash(&position))
+}
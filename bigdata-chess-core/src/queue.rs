@@ -1,8 +1,9 @@
 use rdkafka::producer::Producer;
 
 use {
-    std::time::Duration,
+    std::{time::{Duration, Instant}, collections::HashMap},
     serde::{Serialize, Deserialize},
+    anyhow::{anyhow, Context, Result},
     prost::Message,
     rand::Rng,
     rdkafka::{
@@ -10,15 +11,39 @@ use {
         client::ClientContext,
         producer::{FutureProducer, FutureRecord},
         consumer::{ConsumerContext, StreamConsumer, Consumer},
+        topic_partition_list::{TopicPartitionList, Offset},
+        Message as KafkaMessage,
+    },
+    crate::{
+        config::QueueConfig,
+        data::GameParserError,
+        lichess::{LichessDataFileInfo, LichessVariant},
     },
-    crate::config::QueueConfig,
 };
 
+pub const TOPIC_LICHESS_DATA_FILES: &str = "chess-lichess-data-files";
 pub const TOPIC_LICHESS_DATA_FILES_SYNCED: &str = "chess-lichess-data-files-synced";
 pub const TOPIC_LICHESS_RAW_GAMES: &str = "chess-lichess-raw-games";
+pub const TOPIC_LICHESS_RAW_GAMES_REJECTS: &str = "chess-lichess-raw-games-rejects";
 pub const TOPIC_CHESS_GAMES: &str = "chess-games";
+pub const TOPIC_CHESS_GAME_JUDGEMENTS: &str = "chess-game-judgements";
+pub const TOPIC_CHESS_GAME_RATINGS: &str = "chess-game-ratings";
 pub const TOPIC_CHESS_GAME_PARSER_ERRORS: &str = "chess-game-parser-errors";
+pub const TOPIC_CHESS_GAME_PARSER_ERRORS_UNRESOLVED: &str = "chess-game-parser-errors-unresolved";
 pub const TOPIC_CHESS_LOGS: &str = "chess-logs";
+pub const TOPIC_CHUNK_SPLITTER_STATE: &str = "chess-chunk-splitter-state";
+
+// reading compacted topic fails when it does not get closer to the end of the topic for this long
+const COMPACTED_TOPIC_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+// games of non-standard variants go to separate topics, so that they never get mixed into standard analytics
+pub fn topic_for_variant(topic: &str, variant: LichessVariant) -> String {
+    if variant.is_standard() {
+        topic.to_owned()
+    } else {
+        format!("{}-{}", topic, variant.name())
+    }
+}
 
 pub struct Queue {
     kafka_endpoint: String,
@@ -31,12 +56,30 @@ impl ClientContext for StreamingContext {}
 
 impl ConsumerContext for StreamingContext {}
 
+// message in chess-lichess-data-files. Older messages contain only url as plain string.
+#[derive(Serialize, Deserialize)]
+pub struct LichessDataFileMessage {
+    url: String,
+    source: Option<LichessDataFileInfo>,
+    #[serde(default)]
+    changed: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SyncedFileMessage {
     path: String,
     total_chunks: u64,
 }
 
+// position of chunk splitter in a data file, keyed by data file path in compacted state topic.
+// Written in the same transaction as games produced up to this position.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChunkSplitterState {
+    path: String,
+    byte_offset: u64,
+    game_index: u64,
+}
+
 impl Queue {
     pub fn new(config: &QueueConfig) -> Self {
         Self {
@@ -65,10 +108,106 @@ impl Queue {
             .unwrap()
     }
 
+    // for steps which take a long time to process a single message and commit offsets themselves when done
+    pub fn consumer_with_manual_commit(&self, group_id: &str, max_poll_interval: Duration) -> StreamConsumer<StreamingContext> {
+        ClientConfig::new()
+            .set("group.id", group_id)
+            .set("bootstrap.servers", self.kafka_endpoint())
+            .set("enable.partition.eof", "false")
+            .set("session.timeout.ms", "6000")
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "beginning")
+            .set("max.poll.interval.ms", max_poll_interval.as_millis().to_string())
+            .create_with_context(StreamingContext)
+            .unwrap()
+    }
+
     pub fn transactional_producer(&self, transactional_id: &str) -> FutureProducer {
         transactional_producer(&self.kafka_endpoint, transactional_id)
     }
 
+    // reads compacted topic up to its end, latest payload for each key wins. Tombstones remove keys.
+    pub async fn read_compacted_topic(&self, topic: &str) -> Result<HashMap<Vec<u8>, Vec<u8>>> {
+        let consumer: StreamConsumer<StreamingContext> = ClientConfig::new()
+            .set("group.id", format!("{}-reader", topic))
+            .set("bootstrap.servers", self.kafka_endpoint())
+            .set("enable.partition.eof", "false")
+            .set("enable.auto.commit", "false")
+            .set("isolation.level", "read_committed")
+            .create_with_context(StreamingContext)?;
+
+        let metadata = consumer.fetch_metadata(Some(topic), Duration::from_secs(10))
+            .with_context(|| format!("failed to fetch metadata of {}", topic))?;
+        let mut assignment = TopicPartitionList::new();
+        let mut high_watermarks = HashMap::new();
+        for partition in metadata.topics().iter().flat_map(|v| v.partitions().iter()) {
+            let (low, high) = consumer.fetch_watermarks(topic, partition.id(), Duration::from_secs(10))
+                .with_context(|| format!("failed to fetch watermarks of {} partition {}", topic, partition.id()))?;
+            if high > low {
+                assignment.add_partition_offset(topic, partition.id(), Offset::Beginning)?;
+                high_watermarks.insert(partition.id(), high);
+            }
+        }
+
+        let mut result = HashMap::new();
+        if high_watermarks.is_empty() {
+            return Ok(result);
+        }
+        consumer.assign(&assignment)?;
+
+        // transaction markers take offsets too, so end of partition may never be observed as a message. Consumer
+        // position moves past them, so it is checked when no messages arrive.
+        let mut last_progress_at = Instant::now();
+        while !high_watermarks.is_empty() {
+            let message = match tokio::time::timeout(Duration::from_secs(1), consumer.recv()).await {
+                Ok(message) => message?,
+                Err(_) => {
+                    for element in consumer.position()?.elements_for_topic(topic) {
+                        let reached_end = match (element.offset(), high_watermarks.get(&element.partition())) {
+                            (Offset::Offset(position), Some(high)) => position >= *high,
+                            _ => false,
+                        };
+                        if reached_end {
+                            high_watermarks.remove(&element.partition());
+                            last_progress_at = Instant::now();
+                        }
+                    }
+
+                    if !high_watermarks.is_empty() && last_progress_at.elapsed() > COMPACTED_TOPIC_READ_TIMEOUT {
+                        return Err(anyhow!(
+                            "partitions {:?} of {} did not reach their end in {} seconds",
+                            high_watermarks.keys().collect::<Vec<_>>(),
+                            topic,
+                            COMPACTED_TOPIC_READ_TIMEOUT.as_secs(),
+                        ));
+                    }
+                    continue;
+                },
+            };
+            last_progress_at = Instant::now();
+
+            let key = message.key().unwrap_or(&[]).to_vec();
+            match message.payload() {
+                Some(payload) => result.insert(key, payload.to_vec()),
+                None => result.remove(&key),
+            };
+
+            if high_watermarks.get(&message.partition()).map(|high| message.offset() + 1 >= *high).unwrap_or(false) {
+                high_watermarks.remove(&message.partition());
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub async fn chunk_splitter_state(&self, state_topic: &str) -> Result<HashMap<String, ChunkSplitterState>> {
+        Ok(self.read_compacted_topic(state_topic).await?
+            .into_values()
+            .filter_map(|v| serde_json::from_slice::<ChunkSplitterState>(&v).ok())
+            .map(|v| (v.path.clone(), v))
+            .collect())
+    }
+
     pub fn consumer_for_topic(&self, group_id: &str, topic: &str) -> StreamConsumer<StreamingContext> {
         let consumer = self.consumer(group_id);
         consumer.subscribe(&vec![topic]).unwrap();
@@ -79,7 +218,7 @@ impl Queue {
         self.producer.send(message, Duration::from_secs(32)).await.unwrap();
     }
 
-    pub async fn send_game_parser_error(&self, error: String) {
+    pub async fn send_game_parser_error(&self, error: GameParserError) {
         self.send_message(
             FutureRecord::to(TOPIC_CHESS_GAME_PARSER_ERRORS)
                 .payload(&error.encode_to_vec())
@@ -96,6 +235,37 @@ impl Queue {
     }
 }
 
+impl LichessDataFileMessage {
+    pub fn new(url: String, source: LichessDataFileInfo, changed: bool) -> Self {
+        Self {
+            url,
+            source: Some(source),
+            changed,
+        }
+    }
+
+    pub fn from_payload(payload: &[u8]) -> Self {
+        serde_json::from_slice(payload).unwrap_or_else(|_| Self {
+            url: String::from_utf8_lossy(payload).to_string(),
+            source: None,
+            changed: false,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn source(&self) -> Option<&LichessDataFileInfo> {
+        self.source.as_ref()
+    }
+
+    // lichess re-uploaded this file after it was announced
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+}
+
 impl SyncedFileMessage {
     pub fn new(path: String, total_chunks: u64) -> Self {
         Self {
@@ -113,6 +283,30 @@ impl SyncedFileMessage {
     }
 }
 
+impl ChunkSplitterState {
+    pub fn new(path: String, byte_offset: u64, game_index: u64) -> Self {
+        Self {
+            path,
+            byte_offset,
+            game_index,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // offset in decompressed data file, at which splitting can be resumed
+    pub fn byte_offset(&self) -> u64 {
+        self.byte_offset
+    }
+
+    // number of games produced from data file before byte_offset
+    pub fn game_index(&self) -> u64 {
+        self.game_index
+    }
+}
+
 fn producer(endpoint: &str) -> FutureProducer {
     ClientConfig::new()
         .set("bootstrap.servers", endpoint)
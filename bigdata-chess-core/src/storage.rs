@@ -1,12 +1,25 @@
 use {
+    std::collections::HashMap,
     awsregion::Region,
     s3::{Bucket, creds::Credentials},
     serde::{Serialize, Deserialize},
     anyhow::{anyhow, Result},
     reqwest::StatusCode,
-    crate::config::StorageConfig,
+    crate::{
+        config::StorageConfig,
+        lichess::{LichessDataFileInfo, LichessVariant},
+    },
 };
 
+// kinds of game data files, each one is written to its own directory and imported into its own table
+pub const GAME_DATA_GAMES: &str = "games";
+pub const GAME_DATA_MOVES: &str = "moves";
+pub const GAME_DATA_COMMENTS_EVAL: &str = "comments-eval";
+pub const GAME_DATA_CLOCKS: &str = "clocks";
+pub const GAME_DATA_MOVE_JUDGEMENTS: &str = "move-judgements";
+pub const GAME_DATA_POSITIONS: &str = "positions";
+pub const GAME_DATA_RATINGS: &str = "ratings";
+
 pub struct Storage {
     bucket: Bucket,
     remote_api_key: Option<String>,
@@ -14,8 +27,24 @@ pub struct Storage {
 }
 
 #[derive(Serialize, Deserialize)]
-struct LichessDataFileMetadata {
-    total_chunks: u64,
+pub struct LichessDataFileMetadata {
+    pub total_chunks: u64,
+    #[serde(default)]
+    pub source: Option<LichessDataFileInfo>,
+    #[serde(default)]
+    pub chunk_size: Option<u64>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub status: LichessDataFileStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LichessDataFileStatus {
+    Downloading,
+    Synced,
+    // checksum did not match the one published by lichess
+    Quarantined,
 }
 
 impl Storage {
@@ -34,26 +63,15 @@ impl Storage {
         }
     }
 
-    pub async fn put_lichess_data_file_metadata(&self, path: String, total_chunks: u64) {
-        let metadata = serde_json::to_string(&LichessDataFileMetadata {
-            total_chunks,
-        }).unwrap();
+    pub async fn put_lichess_data_file_metadata(&self, path: String, metadata: &LichessDataFileMetadata) {
+        let metadata = serde_json::to_string(metadata).unwrap();
         self.bucket.put_object(format!("{}/metadata", path), metadata.as_bytes()).await.unwrap();
     }
 
-    pub async fn put_lichess_data_file_chunk_splitting_state(&self, path: String, processed_games: u64) {
-        self.bucket.put_object(format!("{}/chunk_splitting_state", path), processed_games.to_string().as_bytes()).await.unwrap();
-    }
-
-    pub async fn get_lichess_data_file_chunk_splitting_state(&self, path: String) -> u64 {
-        self.bucket.get_object(format!("{}/chunk_splitting_state", path)).await
+    pub async fn get_lichess_data_file_metadata(&self, path: String) -> Option<LichessDataFileMetadata> {
+        self.bucket.get_object(format!("{}/metadata", path)).await
             .ok()
-            .and_then(|v| v.as_str().unwrap().parse().ok())
-            .unwrap_or(0)
-    }
-
-    pub async fn is_lichess_data_file_metadata_present(&self, path: String) -> bool {
-        self.bucket.get_object(format!("{}/metadata", path)).await.is_ok()
+            .and_then(|v| serde_json::from_slice(&v.to_vec()).ok())
     }
 
     pub async fn upload_lichess_data_file_chunk(&self, path: String, chunk_index: u64, data: &[u8]) {
@@ -70,30 +88,23 @@ impl Storage {
             .map_err(|err| anyhow!("failed to get lichess data file chunk: {:?}", err))
     }
 
-    pub async fn put_game_data_file(&self, key: &str, data: Vec<u8>) {
-        self.bucket.put_object(format!("game-data/games/{}", key), &data).await.unwrap();
-    }
-
-    pub async fn put_game_moves_data_file(&self, key: &str, data: Vec<u8>) {
-        self.bucket.put_object(format!("game-data/moves/{}", key), &data).await.unwrap();
-    }
-
-    pub async fn put_game_comment_eval_data_file(&self, key: &str, data: Vec<u8>) {
-        self.bucket.put_object(format!("game-data/comments-eval/{}", key), &data).await.unwrap();
+    pub async fn put_announced_lichess_data_files(&self, files: &HashMap<String, LichessDataFileInfo>) {
+        self.bucket.put_object("update-checker/announced-files", &serde_json::to_vec(files).unwrap()).await.unwrap();
     }
 
-    pub async fn remote_list_game_data_files(&self) -> Result<Vec<String>> {
-        let res = self.remote_api_request("http://storage.nikitavbv.com/v1/chess-data/game-data/games").await?;
-        Ok(res.json().await.unwrap())
+    pub async fn get_announced_lichess_data_files(&self) -> HashMap<String, LichessDataFileInfo> {
+        self.bucket.get_object("update-checker/announced-files").await
+            .ok()
+            .and_then(|v| serde_json::from_slice(&v.to_vec()).ok())
+            .unwrap_or_default()
     }
 
-    pub async fn remote_list_game_moves_files(&self) -> Result<Vec<String>> {
-        let res = self.remote_api_request("http://storage.nikitavbv.com/v1/chess-data/game-data/moves").await?;
-        Ok(res.json().await.unwrap())
+    pub async fn put_game_data_file(&self, variant: LichessVariant, kind: &str, key: &str, data: Vec<u8>) {
+        self.bucket.put_object(format!("{}/{}/{}", game_data_prefix(variant), kind, key), &data).await.unwrap();
     }
 
-    pub async fn remote_list_game_comment_eval_files(&self) -> Result<Vec<String>> {
-        let res = self.remote_api_request("http://storage.nikitavbv.com/v1/chess-data/game-data/comments-eval").await?;
+    pub async fn remote_list_game_data_files(&self, kind: &str) -> Result<Vec<String>> {
+        let res = self.remote_api_request(&format!("http://storage.nikitavbv.com/v1/chess-data/game-data/{}", kind)).await?;
         Ok(res.json().await.unwrap())
     }
 
@@ -123,6 +134,22 @@ impl Storage {
     }
 }
 
+// games of non-standard variants are stored separately, so that they are not imported into standard tables
+fn game_data_prefix(variant: LichessVariant) -> String {
+    if variant.is_standard() {
+        "game-data".to_owned()
+    } else {
+        format!("game-data/{}", variant.name())
+    }
+}
+
+// metadata written before download status was tracked only exists for files which were fully downloaded
+impl Default for LichessDataFileStatus {
+    fn default() -> Self {
+        Self::Synced
+    }
+}
+
 fn credentials(config: &StorageConfig) -> Credentials {
     Credentials::new(Some(config.access_key().unwrap()), Some(config.secret_key().unwrap()), None, None, None).unwrap()
 }
\ No newline at end of file
@@ -0,0 +1,14 @@
+use leveldb::database::key::Key;
+
+// leveldb key made of arbitrary bytes, for composite keys which are read with prefix scans
+pub struct ByteKey(pub Vec<u8>);
+
+impl Key for ByteKey {
+    fn from_u8(key: &[u8]) -> Self {
+        Self(key.to_vec())
+    }
+
+    fn as_slice<T, F: Fn(&[u8]) -> T>(&self, f: F) -> T {
+        f(&self.0)
+    }
+}
@@ -0,0 +1,161 @@
+use {
+    std::{
+        sync::Arc,
+        io::{self, Read, BufRead},
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+        fmt,
+        error::Error,
+    },
+    tracing::{info, warn},
+    tokio::{sync::mpsc, task::JoinHandle, time::sleep},
+    futures::{
+        ready,
+        stream,
+        StreamExt,
+        io::{AsyncRead, AsyncBufRead},
+    },
+    bigdata_chess_core::storage::Storage,
+};
+
+// Streams lichess data file from object storage chunk by chunk. Next chunks are fetched concurrently in background,
+// so at most prefetch_chunks + 2 chunks are kept in memory at any time.
+pub struct LichessDataFileChunkReader {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+    fetch_task: JoinHandle<()>,
+}
+
+impl LichessDataFileChunkReader {
+    pub fn new(storage: Arc<Storage>, path: String, total_chunks: u64, prefetch_chunks: usize, retries: u32) -> Self {
+        let (sender, receiver) = mpsc::channel(1);
+
+        let fetch_task = tokio::spawn(async move {
+            let mut chunks = stream::iter(0..total_chunks)
+                .map(|chunk_index| fetch_chunk(storage.clone(), path.clone(), chunk_index, total_chunks, retries))
+                .buffered(prefetch_chunks.max(1));
+
+            while let Some(chunk) = chunks.next().await {
+                let failed = chunk.is_err();
+                if sender.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            chunks: receiver,
+            chunk: Vec::new(),
+            position: 0,
+            fetch_task,
+        }
+    }
+}
+
+impl Drop for LichessDataFileChunkReader {
+    fn drop(&mut self) {
+        self.fetch_task.abort();
+    }
+}
+
+impl AsyncBufRead for LichessDataFileChunkReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+
+        while this.position >= this.chunk.len() {
+            match ready!(this.chunks.poll_recv(cx)) {
+                Some(Ok(chunk)) => {
+                    this.chunk = chunk;
+                    this.position = 0;
+                },
+                Some(Err(err)) => return Poll::Ready(Err(err)),
+                None => return Poll::Ready(Ok(&[])),
+            }
+        }
+
+        Poll::Ready(Ok(&this.chunk[this.position..]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().position += amt;
+    }
+}
+
+impl AsyncRead for LichessDataFileChunkReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let available = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        AsyncBufRead::consume(self, len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+// blocking reads are for use from spawn_blocking, they will panic if called from async context
+impl BufRead for LichessDataFileChunkReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.position >= self.chunk.len() {
+            match self.chunks.blocking_recv() {
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                },
+                Some(Err(err)) => return Err(err),
+                None => return Ok(&[]),
+            }
+        }
+
+        Ok(&self.chunk[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position += amt;
+    }
+}
+
+impl Read for LichessDataFileChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        BufRead::consume(self, len);
+        Ok(len)
+    }
+}
+
+async fn fetch_chunk(storage: Arc<Storage>, path: String, chunk_index: u64, total_chunks: u64, retries: u32) -> io::Result<Vec<u8>> {
+    let mut attempt = 0;
+
+    loop {
+        match storage.get_lichess_data_file_chunk(&path, chunk_index).await {
+            Ok(chunk) => {
+                info!("fetched chunk {}/{}", chunk_index, total_chunks);
+                return Ok(chunk);
+            },
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                warn!("failed to fetch chunk {}/{} (attempt {}/{}): {}", chunk_index, total_chunks, attempt, retries, err);
+                sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            },
+            Err(err) => return Err(io::Error::new(io::ErrorKind::Other, ChunkFetchError(format!("failed to fetch chunk {}/{}: {}", chunk_index, total_chunks, err)))),
+        }
+    }
+}
+
+// unlike errors of decompression, this is not a problem of the file itself, so reading it can be retried later
+#[derive(Debug)]
+struct ChunkFetchError(String);
+
+impl fmt::Display for ChunkFetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ChunkFetchError {}
+
+pub fn is_chunk_fetch_error(err: &io::Error) -> bool {
+    err.get_ref().map(|v| v.is::<ChunkFetchError>()).unwrap_or(false)
+}
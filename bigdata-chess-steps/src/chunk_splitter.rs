@@ -2,50 +2,44 @@
 
 use {
     std::{
-        sync::Arc, 
-        io::Read, 
-        collections::{hash_map::DefaultHasher, VecDeque},
+        sync::Arc,
+        collections::hash_map::DefaultHasher,
         hash::Hasher,
         time::{Instant, Duration},
     },
-    tracing::{info, error},
+    tracing::{info, warn, error},
     prost::Message,
     rdkafka::{
         Message as KafkaMessage,
-        config::ClientConfig,
-        producer::{FutureRecord, Producer},
-        consumer::{StreamConsumer, CommitMode, Consumer},
+        producer::{FutureRecord, FutureProducer, Producer},
+        consumer::{StreamConsumer, Consumer},
+        topic_partition_list::{TopicPartitionList, Offset},
+        error::KafkaResult,
     },
-    rand::{Rng, distributions::Alphanumeric},
+    tokio::time::sleep,
+    futures::io::AsyncReadExt,
+    async_compression::futures::bufread::ZstdDecoder,
     bigdata_chess_core::{
         storage::Storage,
-        queue::{Queue, StreamingContext, TOPIC_LICHESS_DATA_FILES_SYNCED, SyncedFileMessage, TOPIC_LICHESS_RAW_GAMES},
-        data::RawChessGame,
+        queue::{Queue, StreamingContext, TOPIC_LICHESS_DATA_FILES_SYNCED, SyncedFileMessage, ChunkSplitterState, topic_for_variant},
+        lichess::{LichessDataFile, LichessVariant},
         config::ChunkSplitterStepConfig,
     },
-    crate::progress::Progress,
+    crate::{
+        chunk_reader::{LichessDataFileChunkReader, is_chunk_fetch_error},
+        pgn_splitter::{PgnSplitter, PgnFragment},
+        progress::Progress,
+    },
 };
 
 pub async fn chunk_splitter_step(config: &ChunkSplitterStepConfig, storage: Arc<Storage>, queue: Arc<Queue>) -> std::io::Result<()> {
     info!("hello from chunk splitter!");
 
-    let consumer: StreamConsumer<StreamingContext> = ClientConfig::new()
-        .set("group.id", config.group_id())
-        .set("bootstrap.servers", queue.kafka_endpoint())
-        .set("enable.partition.eof", "false")
-        .set("session.timeout.ms", "6000")
-        .set("enable.auto.commit", "false")
-        .set("auto.offset.reset", "beginning")
-        .set("max.poll.interval.ms", "3000000")
-        .create_with_context(StreamingContext)
-        .unwrap();
+    let consumer = queue.consumer_with_manual_commit(&config.group_id(), config.max_poll_interval());
     consumer.subscribe(&vec![TOPIC_LICHESS_DATA_FILES_SYNCED]).unwrap();
 
-    let producer = queue.transactional_producer(&format!("chunk-splitter-{}", random_transactional_id()));
-
-    let to_topic = config.to_topic().clone();
-
-    let mut progress = Progress::new("skipping".to_owned());
+    let producer = queue.transactional_producer(&config.transactional_id());
+    let state_topic = config.state_topic();
 
     let mut output_batch = Vec::new();
 
@@ -54,125 +48,114 @@ pub async fn chunk_splitter_step(config: &ChunkSplitterStepConfig, storage: Arc<
         let payload = msg.payload().unwrap();
         let payload: SyncedFileMessage = serde_json::from_slice(payload).unwrap();
 
-        info!("processing file {}", payload.path());
-        let reader = LichessDataFileChunkReader::new(storage.clone(), payload.path().to_owned(), payload.total_chunks());
-        let data = reader.read().await;
+        // offset of the file message itself is committed while file is being processed, so that it is received again after restart
+        let mut input_offsets = TopicPartitionList::new();
+        input_offsets.add_partition_offset(msg.topic(), msg.partition(), Offset::Offset(msg.offset())).unwrap();
+        let mut done_offsets = TopicPartitionList::new();
+        done_offsets.add_partition_offset(msg.topic(), msg.partition(), Offset::Offset(msg.offset() + 1)).unwrap();
 
-        let mut decoder = zstd::Decoder::new(data).unwrap();
-        
-        let mut pgn = String::new();
-        let mut buf = vec![0; 1024];
+        info!("processing file {}", payload.path());
+        let variant = LichessDataFile::from_url(payload.path()).map(|v| v.variant).unwrap_or(LichessVariant::Standard);
+        let to_topic = topic_for_variant(&config.to_topic(), variant);
+        let rejects_topic = topic_for_variant(&config.rejects_topic(), variant);
+        let reader = LichessDataFileChunkReader::new(
+            storage.clone(),
+            payload.path().to_owned(),
+            payload.total_chunks(),
+            config.prefetch_chunks(),
+            config.chunk_fetch_retries(),
+        );
+        let mut decoder = ZstdDecoder::new(reader);
+
+        let mut buf = vec![0; 64 * 1024];
 
         let mut time_total: f64 = 0.0;
         let mut time_io: f64 = 0.0;
         let mut time_decompress: f64 = 0.0;
 
-        let mut games_produced = 0;
-        let games_to_skip = storage.get_lichess_data_file_chunk_splitting_state(payload.path().to_owned()).await;
-        let mut state_sync_time = Instant::now();
-
-        // let mut message_join_handles = VecDeque::new();
+        let state = queue.chunk_splitter_state(&state_topic)
+            .await
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, format!("failed to read chunk splitter state: {:#}", err)))?
+            .remove(payload.path());
+        let byte_offset = state.as_ref().map(|v| v.byte_offset()).unwrap_or(0);
+        let mut games_produced = state.as_ref().map(|v| v.game_index()).unwrap_or(0);
+        let mut malformed_fragments = 0;
+
+        if byte_offset > 0 {
+            info!("resuming from byte {} after {} games, skipping decompressed data before it", byte_offset, games_produced);
+            let mut skipped = 0;
+            while skipped < byte_offset {
+                let len = buf.len().min((byte_offset - skipped) as usize);
+                let res = match decoder.read(&mut buf[..len]).await {
+                    Ok(v) => v,
+                    Err(err) if is_chunk_fetch_error(&err) => return Err(err),
+                    // reported when splitting, as the same error is returned by the next read
+                    Err(_) => 0,
+                };
+                if res == 0 {
+                    break;
+                }
+                skipped += res as u64;
+            }
+        }
 
-        info!("skipping {} games", games_to_skip);
+        let mut splitter = PgnSplitter::starting_at(byte_offset);
+        let mut progress = Progress::new("processing games".to_owned());
 
         loop {
             let started_at = Instant::now();
 
             let uncompress_started_at = Instant::now();
-            let res = decoder.read(&mut buf).unwrap();
+            let res = match decoder.read(&mut buf).await {
+                Ok(v) => v,
+                // message is not committed, so that splitting is resumed from the last committed state after restart
+                Err(err) if is_chunk_fetch_error(&err) => return Err(err),
+                Err(err) => {
+                    // rest of the file cannot be decompressed, so the game being split goes to rejects as malformed
+                    error!("failed to decompress {}, finishing it: {}", payload.path(), err);
+                    0
+                },
+            };
             time_decompress += (Instant::now() - uncompress_started_at).as_secs_f64();
-            pgn.push_str(&String::from_utf8_lossy(&buf));
-
-            let mut found_something = true;
-
-            if games_produced == games_to_skip {
-                info!("skipped {} games", games_to_skip);
-                progress = Progress::new("processing games".to_owned());
-                time_total = 0.0;
-            }
 
-            while found_something {
-                if let Some(metadata_end) = pgn.find("\n\n") {
-                    let metadata = &pgn[0..metadata_end];
-                    let after_metadata = &pgn[metadata_end+2..];
-    
-                    if let Some(moves_end) = after_metadata.find("\n\n") {
-                        let moves = &after_metadata[0..moves_end];
-    
-                        let metadata = metadata.to_owned();
-                        let moves = moves.to_owned();
-                        pgn.drain(0..metadata_end+2+moves_end+2);
-                        
-                        let game = RawChessGame {
-                            metadata,
-                            moves,
-                        };
+            let fragments = if res == 0 {
+                splitter.finish()
+            } else {
+                splitter.push(&buf[..res])
+            };
 
+            for (fragment, fragment_end_offset) in fragments {
+                match fragment {
+                    PgnFragment::Game(game) => {
                         let encoded_game = game.encode_to_vec();
 
                         let mut hasher = DefaultHasher::new();
                         hasher.write(&encoded_game);
 
                         games_produced += 1;
+                        output_batch.push((to_topic.clone(), hasher.finish().encode_to_vec(), encoded_game));
 
-                        if games_produced > games_to_skip {
-                            output_batch.push((hasher.finish().encode_to_vec(), encoded_game));
-
-                            if output_batch.len() >= 16 {
-                                let io_started_at = Instant::now();
-                                producer.begin_transaction().unwrap();
-                                for (key, value) in &output_batch {
-                                    producer.send(FutureRecord::to(&to_topic)
-                                        .payload(&value)
-                                        .key(&key), Duration::from_secs(10))
-                                        .await
-                                        .unwrap();
-                                }
-                                producer.commit_transaction(Duration::from_secs(10)).unwrap();
-                                output_batch.clear();
-                                time_io += (Instant::now() - io_started_at).as_secs_f64();
-                            }
-
-                            /*let io_started_at = Instant::now();
-
-                            let queue = queue.clone();
-                            let to_topic = to_topic.clone();
-                            let message_future = async move {
-                                queue.send_message(
-                                    FutureRecord::to(&to_topic)
-                                        .payload(&encoded_game)
-                                        .key(&hasher.finish().encode_to_vec())).await
-                            };
-                            let task_join_handle = tokio::spawn(message_future);
-                            message_join_handles.push_back(task_join_handle);
-    
-                            while message_join_handles.len() >= 16 {
-                                message_join_handles.pop_front().unwrap().await.unwrap();
-                            }
-    
-                            time_io += (Instant::now() - io_started_at).as_secs_f64();*/
-
-                            let now = Instant::now();
-                            if (now - state_sync_time).as_secs_f32() > 60.0 {
-                                storage.put_lichess_data_file_chunk_splitting_state(payload.path().to_owned(), games_produced).await;
-                                state_sync_time = now;
-                            }
-
-                            if progress.update() {
-                                info!("time_total: {}", time_total.round());
-                                info!("time_io: {}", time_io.round());
-                            }
-                        } else {
-                            if progress.update() {
-                                info!("time_total: {}", time_total.round());
-                                info!("time_decompress: {}", time_decompress.round());
-                            }
+                        if progress.update() {
+                            info!("time_total: {}", time_total.round());
+                            info!("time_io: {}", time_io.round());
+                            info!("time_decompress: {}", time_decompress.round());
                         }
-                    } else {
-                        found_something = false;
-                    }
-                } else {
-                    found_something = false;
+                    },
+                    PgnFragment::Malformed(fragment) => {
+                        malformed_fragments += 1;
+                        warn!("found malformed pgn fragment in {} ({} so far)", payload.path(), malformed_fragments);
+
+                        let mut hasher = DefaultHasher::new();
+                        hasher.write(&fragment);
+                        output_batch.push((rejects_topic.clone(), hasher.finish().encode_to_vec(), fragment));
+                    },
+                };
+
+                if output_batch.len() >= 16 {
+                    let io_started_at = Instant::now();
+                    let state = ChunkSplitterState::new(payload.path().to_owned(), fragment_end_offset, games_produced);
+                    send_batch_with_retries(&producer, &consumer, config, &mut output_batch, payload.path(), Some(&state), &input_offsets).await?;
+                    time_io += (Instant::now() - io_started_at).as_secs_f64();
                 }
             }
 
@@ -183,49 +166,78 @@ pub async fn chunk_splitter_step(config: &ChunkSplitterStepConfig, storage: Arc<
             }
         }
 
-        info!("done processing file {}", payload.path());
+        // remaining games, state removal and offset of the file message go in one transaction. State is removed,
+        // so that file is split from the beginning if it is downloaded again.
+        send_batch_with_retries(&producer, &consumer, config, &mut output_batch, payload.path(), None, &done_offsets).await?;
+
+        if malformed_fragments > 0 {
+            warn!("{} malformed pgn fragments in {}, sent them to {}", malformed_fragments, payload.path(), rejects_topic);
+        }
 
-        consumer.commit_message(&msg, CommitMode::Sync).unwrap();
+        info!("done processing file {}, total games: {}", payload.path(), games_produced);
     }
 }
 
-pub struct LichessDataFileChunkReader {
-    storage: Arc<Storage>,
-    path: String,
-    total_chunks: u64,
-
-    chunk_buffer: Vec<u8>,
-    current_chunk: Option<u64>,
-}
+// games, splitter position and consumer offset are committed atomically, so that after restart splitting continues
+// right after the last committed game. Failed transactions are aborted and sent again.
+async fn send_batch_with_retries(
+    producer: &FutureProducer,
+    consumer: &StreamConsumer<StreamingContext>,
+    config: &ChunkSplitterStepConfig,
+    batch: &mut Vec<(String, Vec<u8>, Vec<u8>)>,
+    path: &str,
+    state: Option<&ChunkSplitterState>,
+    input_offsets: &TopicPartitionList,
+) -> std::io::Result<()> {
+    let send_retries = config.send_retries();
+    let mut attempt = 0;
+    while let Err(err) = send_batch(producer, consumer, batch, &config.state_topic(), path, state, input_offsets).await {
+        if let Err(err) = producer.abort_transaction(Duration::from_secs(10)) {
+            error!("failed to abort transaction: {}", err);
+        }
 
-impl LichessDataFileChunkReader {
-    pub fn new(storage: Arc<Storage>, path: String, total_chunks: u64) -> Self {
-        Self {
-            storage,
-            path,
-            total_chunks,
-            chunk_buffer: Vec::new(),
-            current_chunk: None,
+        if attempt >= send_retries {
+            return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("failed to send batch of games from {}: {}", path, err)));
         }
+        attempt += 1;
+        warn!("failed to send batch of games from {} (attempt {}/{}): {}", path, attempt, send_retries, err);
+        sleep(Duration::from_secs(2u64.pow(attempt))).await;
     }
+    batch.clear();
 
-    pub async fn read(self) -> VecDeque<u8> {
-        let mut res = VecDeque::new();
-
-        for chunk in 0..self.total_chunks {
-            info!("fetching chunk {}/{}", chunk, self.total_chunks);
-            let mut chunk = self.storage.get_lichess_data_file_chunk(&self.path, chunk).await.unwrap();
-            res.append(&mut chunk.into());
-        }
+    Ok(())
+}
 
-        res
+async fn send_batch(
+    producer: &FutureProducer,
+    consumer: &StreamConsumer<StreamingContext>,
+    batch: &[(String, Vec<u8>, Vec<u8>)],
+    state_topic: &str,
+    path: &str,
+    state: Option<&ChunkSplitterState>,
+    input_offsets: &TopicPartitionList,
+) -> KafkaResult<()> {
+    producer.begin_transaction()?;
+    for (topic, key, value) in batch.iter() {
+        producer.send(FutureRecord::to(topic)
+            .payload(value)
+            .key(key), Duration::from_secs(10))
+            .await
+            .map_err(|(err, _)| err)?;
     }
-}
 
-fn random_transactional_id() -> String {
-    rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(16)
-        .map(char::from)
-        .collect()
-}
\ No newline at end of file
+    match state {
+        Some(state) => producer.send(FutureRecord::to(state_topic)
+            .payload(&serde_json::to_vec(state).unwrap())
+            .key(path), Duration::from_secs(10))
+            .await
+            .map_err(|(err, _)| err)?,
+        None => producer.send(FutureRecord::<str, [u8]>::to(state_topic)
+            .key(path), Duration::from_secs(10))
+            .await
+            .map_err(|(err, _)| err)?,
+    };
+
+    producer.send_offsets_to_transaction(input_offsets, &consumer.group_metadata().unwrap(), Duration::from_secs(10))?;
+    producer.commit_transaction(Duration::from_secs(10))
+}
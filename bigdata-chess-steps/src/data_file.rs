@@ -0,0 +1,38 @@
+use {
+    serde::Serialize,
+    tracing::info,
+    rand::{Rng, distributions::Alphanumeric},
+    bigdata_chess_core::{
+        lichess::LichessVariant,
+        storage::Storage,
+    },
+};
+
+// writes rows as csv without headers (hive tables match columns by position) into a new game data file of the given
+// kind, returns its key
+pub async fn write_csv_data_file<T: Serialize>(storage: &Storage, variant: LichessVariant, kind: &str, rows: &[T]) -> String {
+    let mut output_data = Vec::new();
+
+    {
+        let mut csv_writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(&mut output_data);
+        for row in rows {
+            csv_writer.serialize(row).unwrap();
+        }
+    }
+
+    let key = generate_data_file_key();
+    storage.put_game_data_file(variant, kind, &key, output_data).await;
+    info!("uploaded {} data file with key: {}", kind, key);
+
+    key
+}
+
+pub fn generate_data_file_key() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
@@ -1,108 +1,287 @@
 use {
     std::{time::Duration, sync::Arc},
-    tracing::info,
+    tracing::{info, warn, error},
     rdkafka::{
-        config::ClientConfig,
-        consumer::{StreamConsumer, CommitMode, Consumer},
-        producer::{FutureProducer, FutureRecord},
+        consumer::{CommitMode, Consumer},
+        producer::FutureRecord,
         Message,
     },
+    reqwest::{Client, Response, StatusCode, header::{CONTENT_RANGE, RANGE}},
+    tokio::time::sleep,
+    sha2::{Sha256, Digest},
     url::Url,
     futures_util::StreamExt,
     bigdata_chess_core::{
-        queue::{TOPIC_LICHESS_DATA_FILES_SYNCED, SyncedFileMessage, StreamingContext},
-        storage::Storage,
+        config::FileDownloaderStepConfig,
+        lichess::{Lichess, LichessDataFile},
+        queue::{Queue, SyncedFileMessage, LichessDataFileMessage},
+        storage::{Storage, LichessDataFileMetadata, LichessDataFileStatus},
     },
 };
 
-#[allow(dead_code)] // used from other crate
-pub async fn file_downloader_step(storage: Arc<Storage>, bootstrap_servers: String) -> std::io::Result<()> {
+pub async fn file_downloader_step(config: &FileDownloaderStepConfig, lichess: Arc<Lichess>, storage: Arc<Storage>, queue: Arc<Queue>) -> std::io::Result<()> {
     info!("running file downloader step");
-    
-    let consumer: StreamConsumer<StreamingContext> = ClientConfig::new()
-        .set("group.id", "bigdata-chess-file-downloader")
-        .set("bootstrap.servers", &bootstrap_servers)
-        .set("enable.partition.eof", "false")
-        .set("session.timeout.ms", "6000")
-        .set("enable.auto.commit", "true")
-        .set("auto.offset.reset", "beginning")
-        .set("max.poll.interval.ms", "3000000") // downloading file takes a lot of time
-        .create_with_context(StreamingContext)
-        .unwrap();
-    
-    consumer.subscribe(&vec!["chess-lichess-data-files"]).unwrap();
-    
-    let producer: FutureProducer = ClientConfig::new()
-        .set("bootstrap.servers", &bootstrap_servers)
-        .set("message.timeout.ms", "5000")
-        .create()
+
+    let consumer = queue.consumer_with_manual_commit(&config.group_id(), config.max_poll_interval());
+    consumer.subscribe(&vec![config.from_topic().as_str()]).unwrap();
+    let to_topic = config.to_topic();
+
+    let producer = queue.producer();
+    let client = Client::builder()
+        .connect_timeout(config.connect_timeout())
+        .build()
         .unwrap();
+    let chunk_target_size = config.chunk_size();
 
     loop {
         let m = consumer.recv().await.unwrap();
-        let payload = m.payload_view::<str>().unwrap().unwrap();
+        let message = LichessDataFileMessage::from_payload(m.payload().unwrap());
+        let url = message.url();
 
-        let path = path_from_url(payload);
+        let path = path_from_url(url);
         let object_storage_path = path.replace(".pgn.zst", "");
 
-        info!("file is: {} and path {}", payload, path);
+        info!("file is: {} and path {}", url, path);
 
-        if storage.is_lichess_data_file_metadata_present(object_storage_path.clone()).await {
-            info!("file already downloaded or being downloaded");
-            continue;
-        }
+        let metadata = storage.get_lichess_data_file_metadata(object_storage_path.clone()).await;
+        let same_source = metadata.as_ref().map(|v| !message.changed() || v.source.as_ref() == message.source()).unwrap_or(false);
 
-        info!("file has not been downloaded yet");
+        // chunks of the previous attempt can be reused only if they were downloaded from the same version of the file with the same chunk size
+        let mut resume_from_chunk = match metadata {
+            Some(metadata) if same_source && metadata.status == LichessDataFileStatus::Synced => {
+                info!("file already downloaded");
+                consumer.commit_message(&m, CommitMode::Sync).unwrap();
+                continue;
+            },
+            Some(metadata) if same_source && metadata.status == LichessDataFileStatus::Quarantined => {
+                warn!("file is quarantined, skipping it");
+                consumer.commit_message(&m, CommitMode::Sync).unwrap();
+                continue;
+            },
+            Some(metadata) if same_source && metadata.chunk_size == Some(chunk_target_size) => {
+                let mut chunk_index = 0;
+                while chunk_index < metadata.total_chunks && storage.is_lichess_data_file_chunk_present(&object_storage_path, chunk_index).await {
+                    chunk_index += 1;
+                }
+                info!("resuming download from chunk {}/{}", chunk_index, metadata.total_chunks);
+                chunk_index
+            },
+            Some(_) => {
+                info!("file was changed in lichess or downloaded with different chunk size, downloading it again");
+                0
+            },
+            None => {
+                info!("file has not been downloaded yet");
+                0
+            },
+        };
 
-        let data = reqwest::get(payload)
+        // message is not committed when download fails, so that it is resumed from the uploaded chunks after restart
+        let response = request_file_with_retries(&client, url, resume_from_chunk * chunk_target_size, config.download_retries())
             .await
-            .unwrap();
+            .map_err(|err| download_error(&path, err))?;
 
-        let chunk_target_size = 100 * 1024 * 1024;
-        let expected_chunks = ((data.content_length().unwrap() as f64) / (chunk_target_size as f64)).ceil() as u64;
-        storage.put_lichess_data_file_metadata(object_storage_path.clone(), expected_chunks).await;
+        let data = match response {
+            FileResponse::Complete => {
+                info!("all {} chunks were uploaded before restart, verifying file", resume_from_chunk);
+                None
+            },
+            FileResponse::Partial { response, total_size } => Some((response, total_size)),
+            FileResponse::Full { response, total_size } => {
+                if resume_from_chunk > 0 {
+                    warn!("server ignored range request, downloading file from the beginning and overwriting {} chunks uploaded before restart", resume_from_chunk);
+                    resume_from_chunk = 0;
+                }
+                Some((response, total_size))
+            },
+        };
 
-        let mut stream = data.bytes_stream();
+        // chunks which were uploaded before restart are read back to compute the digest of the whole file
+        let mut hasher = Sha256::new();
+        for chunk_index in 0..resume_from_chunk {
+            hasher.update(&storage.get_lichess_data_file_chunk(&object_storage_path, chunk_index).await.unwrap());
+        }
 
-        let mut chunk = Vec::new();
-        let mut chunk_index = 0;
+        let mut chunk_index = resume_from_chunk;
 
-        loop {
-            let next = match stream.next().await {
-                Some(v) => v.unwrap(),
-                None => {
-                    if !storage.is_lichess_data_file_chunk_present(&object_storage_path, chunk_index).await {
-                        storage.upload_lichess_data_file_chunk(object_storage_path.clone(), chunk_index, &chunk).await;
-                    }
-                    chunk.clear();
-                    chunk_index += 1;
-                    break;
+        if let Some((data, total_size)) = data {
+            let expected_chunks = ((total_size as f64) / (chunk_target_size as f64)).ceil() as u64;
+            storage.put_lichess_data_file_metadata(object_storage_path.clone(), &LichessDataFileMetadata {
+                total_chunks: expected_chunks,
+                source: message.source().cloned(),
+                chunk_size: Some(chunk_target_size),
+                sha256: None,
+                status: LichessDataFileStatus::Downloading,
+            }).await;
+
+            let mut stream = data.bytes_stream();
+            let mut chunk = Vec::new();
+            let mut stream_retries = 0;
+
+            // every chunk except the last one has exactly chunk_target_size bytes, so that chunk index maps to byte offset
+            loop {
+                let next = match stream.next().await {
+                    Some(Ok(v)) => Some(v),
+                    Some(Err(err)) => {
+                        if stream_retries >= config.download_retries() {
+                            return Err(download_error(&path, format!("connection failed at chunk {}: {}", chunk_index, err)));
+                        }
+                        stream_retries += 1;
+                        warn!("connection failed while downloading {} at chunk {} (attempt {}/{}): {}", path, chunk_index, stream_retries, config.download_retries(), err);
+                        sleep(Duration::from_secs(2u64.pow(stream_retries))).await;
+
+                        // bytes of the chunk which was not uploaded yet are requested again
+                        chunk.clear();
+                        match request_file_with_retries(&client, url, chunk_index * chunk_target_size, config.download_retries()).await {
+                            Ok(FileResponse::Complete) => break,
+                            Ok(FileResponse::Partial { response, .. }) => stream = response.bytes_stream(),
+                            Ok(FileResponse::Full { response, .. }) => {
+                                warn!("server ignored range request, downloading file from the beginning and overwriting {} chunks", chunk_index);
+                                chunk_index = 0;
+                                hasher = Sha256::new();
+                                stream = response.bytes_stream();
+                            },
+                            Err(err) => return Err(download_error(&path, err)),
+                        }
+                        continue;
+                    },
+                    None => None,
+                };
+                if let Some(next) = next.as_ref() {
+                    chunk.extend_from_slice(next);
                 }
-            };
-            chunk.append(&mut next.to_vec());
 
-            if chunk.len() > chunk_target_size {
-                if !storage.is_lichess_data_file_chunk_present(&object_storage_path, chunk_index).await {
+                while chunk.len() as u64 >= chunk_target_size || (next.is_none() && !chunk.is_empty()) {
+                    let rest = chunk.split_off(chunk_target_size.min(chunk.len() as u64) as usize);
+                    hasher.update(&chunk);
                     storage.upload_lichess_data_file_chunk(object_storage_path.clone(), chunk_index, &chunk).await;
+                    chunk = rest;
+                    chunk_index += 1;
+                    info!("writing chunks: {}/{}", chunk_index, expected_chunks);
+                }
+
+                if next.is_none() {
+                    break;
                 }
-                chunk.clear();
-                chunk_index += 1;
-                info!("writing chunks: {}/{}", chunk_index, expected_chunks);
             }
         }
 
-        info!("finished downloading {}, total chunks: {}", path, chunk_index);
+        let sha256 = format!("{:x}", hasher.finalize());
+        info!("finished downloading {}, total chunks: {}, sha256: {}", path, chunk_index, sha256);
+
+        let status = match expected_sha256(&lichess, url).await {
+            Some(expected) if expected == sha256 => LichessDataFileStatus::Synced,
+            Some(expected) => {
+                error!("sha256 of {} does not match the one published by lichess (expected {}, got {}), quarantining it", path, expected, sha256);
+                LichessDataFileStatus::Quarantined
+            },
+            None => {
+                warn!("lichess did not publish sha256 for {}, assuming file is correct", path);
+                LichessDataFileStatus::Synced
+            },
+        };
+
+        storage.put_lichess_data_file_metadata(object_storage_path.clone(), &LichessDataFileMetadata {
+            total_chunks: chunk_index,
+            source: message.source().cloned(),
+            chunk_size: Some(chunk_target_size),
+            sha256: Some(sha256),
+            status,
+        }).await;
 
-        producer.send(
-            FutureRecord::to(TOPIC_LICHESS_DATA_FILES_SYNCED)
-                .payload(&serde_json::to_vec(&SyncedFileMessage::new(object_storage_path.clone(), chunk_index)).unwrap())
-                .key(&object_storage_path), 
-            Duration::from_secs(0)
-        ).await.unwrap();
+        if status == LichessDataFileStatus::Synced {
+            producer.send(
+                FutureRecord::to(&to_topic)
+                    .payload(&serde_json::to_vec(&SyncedFileMessage::new(object_storage_path.clone(), chunk_index)).unwrap())
+                    .key(&object_storage_path),
+                Duration::from_secs(0)
+            ).await.unwrap();
+        }
         consumer.commit_message(&m, CommitMode::Sync).unwrap();
     }
 }
 
+// response to the download request, which asks only for the part of the file after uploaded chunks
+enum FileResponse {
+    // all chunks were uploaded before restart
+    Complete,
+    // rest of the file, starting at the requested offset
+    Partial { response: Response, total_size: u64 },
+    // whole file, either because nothing was uploaded yet or because server does not support range requests
+    Full { response: Response, total_size: u64 },
+}
+
+async fn request_file_with_retries(client: &Client, url: &str, offset: u64, retries: u32) -> Result<FileResponse, String> {
+    let mut attempt = 0;
+
+    loop {
+        match request_file(client, url, offset).await {
+            Ok(v) => return Ok(v),
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                warn!("failed to request {} (attempt {}/{}): {}", url, attempt, retries, err);
+                sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            },
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+async fn request_file(client: &Client, url: &str, offset: u64) -> Result<FileResponse, String> {
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let response = request.send().await.map_err(|err| format!("request failed: {}", err))?;
+
+    match response.status() {
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => Ok(FileResponse::Complete),
+        StatusCode::PARTIAL_CONTENT if offset > 0 => {
+            let (start, total_size) = content_range(&response).ok_or_else(|| "partial content without content range".to_owned())?;
+            if start != offset {
+                return Err(format!("requested range starting at {}, got one starting at {}", offset, start));
+            }
+            Ok(FileResponse::Partial { response, total_size })
+        },
+        StatusCode::OK => {
+            let total_size = response.content_length().ok_or_else(|| "response without content length".to_owned())?;
+            Ok(FileResponse::Full { response, total_size })
+        },
+        status => Err(format!("unexpected response status: {}", status)),
+    }
+}
+
+// start of the range and total size, from something like "bytes 1000-1999/5000"
+fn content_range(response: &Response) -> Option<(u64, u64)> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total_size) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.parse().ok()?, total_size.parse().ok()?))
+}
+
+async fn expected_sha256(lichess: &Lichess, url: &str) -> Option<String> {
+    let file = match LichessDataFile::from_url(url) {
+        Ok(v) => v,
+        Err(err) => {
+            warn!("failed to parse lichess data file url: {}", err);
+            return None;
+        },
+    };
+
+    match lichess.sha256_sums(file.variant).await {
+        Ok(sums) => sums.get(file.file_name()).cloned(),
+        Err(err) => {
+            warn!("failed to get sha256 sums from lichess: {}", err);
+            None
+        },
+    }
+}
+
+fn download_error(path: &str, err: String) -> std::io::Error {
+    error!("failed to download {}: {}", path, err);
+    std::io::Error::new(std::io::ErrorKind::Other, format!("failed to download {}: {}", path, err))
+}
+
 fn path_from_url(url: &str) -> String {
     Url::parse(url).unwrap().path().to_string()
 }
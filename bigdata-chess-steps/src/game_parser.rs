@@ -1,17 +1,36 @@
-// performance: 167 games/sec
+// performance: 167 games/sec with a single worker
 
 use {
-    std::{sync::Arc, time::Instant, collections::VecDeque},
-    tracing::{info, error},
-    rdkafka::{consumer::{Consumer, CommitMode}, Message, producer::FutureRecord},
+    std::{sync::Arc, time::{Instant, Duration}, collections::{VecDeque, HashMap}, fmt, str::FromStr},
+    tracing::{info, warn, error},
+    rdkafka::{
+        consumer::{Consumer, StreamConsumer},
+        producer::{FutureRecord, FutureProducer, Producer},
+        topic_partition_list::{TopicPartitionList, Offset},
+        error::KafkaResult,
+        Message,
+    },
+    tokio::{task::JoinHandle, time::{timeout, sleep}},
+    futures::future::join_all,
     prost::Message as ProstMessage,
     prost_types::Timestamp,
-    pgn_reader::{BufferedReader, Visitor, SanPlus, RawComment},
-    shakmaty::san::Suffix,
+    pgn_reader::{BufferedReader, Visitor, SanPlus, RawComment, Skip},
+    shakmaty::{
+        san::Suffix,
+        fen::{Fen, ParseFenError},
+        uci::Uci,
+        variant::{VariantPosition, Variant as ShakmatyVariant},
+        CastlingMode,
+        EnPassantMode,
+        Move,
+        Position,
+    },
     chrono::{NaiveDate, NaiveDateTime, NaiveTime, Timelike},
-    rand::Rng,
     bigdata_chess_core::{
-        queue::{Queue, TOPIC_LICHESS_RAW_GAMES, TOPIC_CHESS_GAMES},
+        queue::{Queue, StreamingContext, topic_for_variant, TOPIC_CHESS_GAME_PARSER_ERRORS},
+        game_id::{game_id_from_link, game_id_from_content},
+        position::position_hash,
+        lichess::{LichessEvent, LichessSpeed},
         data::{
             RawChessGame, 
             ChessGame, 
@@ -21,6 +40,7 @@ use {
             PlayerTitle,
             GameResult, 
             Timecontrol, 
+            Speed,
             Termination, 
             GameEntry,
             NormalSan,
@@ -34,6 +54,10 @@ use {
             PutSan,
             Nag,
             Comment,
+            Variant,
+            GameParserError,
+            GameParserErrorKind,
+            BoardMove,
         },
         config::GameParserStepConfig,
     },
@@ -41,86 +65,242 @@ use {
 };
 
 pub async fn game_parser_step(config: &GameParserStepConfig, queue: Arc<Queue>) -> std::io::Result<()> {
-    info!("running game parser step");
+    let workers = config.workers();
+    let batch_size = config.batch_size();
+    info!("running game parser step with {} workers", workers);
 
-    let consumer = queue.consumer(&config.group_id());
+    let consumer = queue.consumer_with_manual_commit(&config.group_id(), config.max_poll_interval());
     consumer.subscribe(&vec![config.from_topic().as_str()]).unwrap();
     let to_topic = config.to_topic();
+    let producer = queue.transactional_producer(&config.transactional_id());
+    let send_retries = config.send_retries();
 
     let mut progress = Progress::new("processed games".to_owned());
 
-    let mut message_join_handles = VecDeque::new();
+    // games are parsed on blocking threads, but results are taken in the order messages were received. This way
+    // offsets are committed only after all previous messages of the partition were produced.
+    let mut in_flight: VecDeque<(JoinHandle<Vec<OutputRecord>>, String, i32, i64)> = VecDeque::new();
+    let mut batch = Vec::new();
+    let mut batch_offsets: HashMap<(String, i32), i64> = HashMap::new();
 
     let mut time_total: f64 = 0.0;
     let mut time_message_recv: f64 = 0.0;
-    let mut time_encode_decode: f64 = 0.0;
     let mut time_io: f64 = 0.0;
-    let mut time_commit_offsets: f64 = 0.0;
 
     loop {
         let started_at = Instant::now();
 
         let message_recv_started_at = Instant::now();
-        let msg = consumer.recv().await.unwrap();
-        let payload = msg.payload().unwrap();
+        let msg = match timeout(Duration::from_millis(500), consumer.recv()).await {
+            Ok(msg) => Some(msg.unwrap()),
+            Err(_) => None,
+        };
         time_message_recv += (Instant::now() - message_recv_started_at).as_secs_f64();
 
-        let encode_decode_started_at = Instant::now();
-        let raw_game = RawChessGame::decode(payload).unwrap();
-        let pgn = format!("{}\n\n{}", raw_game.metadata, raw_game.moves);
+        let idle = msg.is_none();
+        if let Some(msg) = msg {
+            let source = (msg.topic().to_owned(), msg.partition(), msg.offset());
+            let payload = msg.payload().unwrap_or(&[]).to_vec();
+            let to_topic = to_topic.clone();
 
-        let mut reader = BufferedReader::new(pgn.as_bytes());
-        let mut visitor = GameVisitor::new();
+            let (topic, partition, offset) = source.clone();
+            in_flight.push_back((tokio::task::spawn_blocking(move || parse_message(&payload, &to_topic, &source)), topic, partition, offset));
+        }
 
-        let game = match reader.read_game(&mut visitor).unwrap().unwrap() {
-            Ok(v) => v.encode_to_vec(),
-            Err(err) => {
-                error!("Failed to read game: {:?} for pgn: {}", err, pgn);
-                for error in err {
-                    queue.send_game_parser_error(error).await;
+        // when there are no new messages, everything parsed so far is produced, so that offsets do not lag behind
+        while in_flight.len() >= workers || (idle && !in_flight.is_empty()) {
+            let (handle, topic, partition, offset) = in_flight.pop_front().unwrap();
+            let mut records = handle.await.unwrap();
+            if records.iter().any(|v| v.is_game) && progress.update() {
+                info!("time_total: {}", time_total.round());
+                info!("time_message_recv: {}", time_message_recv.round());
+                info!("time_io: {}", time_io.round());
+            }
+            batch.append(&mut records);
+            batch_offsets.insert((topic, partition), offset);
+        }
+
+        if batch.len() >= batch_size || (idle && !batch_offsets.is_empty()) {
+            let io_started_at = Instant::now();
+            let mut offsets = TopicPartitionList::new();
+            for ((topic, partition), offset) in batch_offsets.drain() {
+                offsets.add_partition_offset(&topic, partition, Offset::Offset(offset + 1)).unwrap();
+            }
+
+            let mut attempt = 0;
+            while let Err(err) = send_batch(&producer, &consumer, &batch, &offsets).await {
+                if let Err(err) = producer.abort_transaction(Duration::from_secs(10)) {
+                    error!("failed to abort transaction: {}", err);
                 }
-                consumer.commit_message(&msg, CommitMode::Sync).unwrap();
-                continue;
-            },
-        };
-        time_encode_decode += (Instant::now() - encode_decode_started_at).as_secs_f64();
 
-        let io_started_at = Instant::now();
+                if attempt >= send_retries {
+                    return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("failed to send batch of parsed games: {}", err)));
+                }
+                attempt += 1;
+                warn!("failed to send batch of parsed games (attempt {}/{}): {}", attempt, send_retries, err);
+                sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            }
+            batch.clear();
+            time_io += (Instant::now() - io_started_at).as_secs_f64();
+        }
 
-        let queue = queue.clone();
-        let to_topic = to_topic.clone();
-        let message_future = async move {
-            queue.send_message(FutureRecord::to(&to_topic).payload(&game).key(&random_game_key())).await;
-        };
+        time_total += (Instant::now() - started_at).as_secs_f64();
+    }
+}
+
+// games (or parser errors) of the batch and offsets of their raw games are committed in one transaction, so that
+// a batch which failed to send can be sent again without duplicates
+async fn send_batch(
+    producer: &FutureProducer,
+    consumer: &StreamConsumer<StreamingContext>,
+    batch: &[OutputRecord],
+    offsets: &TopicPartitionList,
+) -> KafkaResult<()> {
+    producer.begin_transaction()?;
+
+    let results = join_all(batch.iter().map(|record| producer.send(
+        FutureRecord::to(&record.topic)
+            .payload(&record.payload)
+            .key(&record.key),
+        Duration::from_secs(0)
+    ))).await;
+    for result in results {
+        result.map_err(|(err, _)| err)?;
+    }
+
+    producer.send_offsets_to_transaction(offsets, &consumer.group_metadata().unwrap(), Duration::from_secs(10))?;
+    producer.commit_transaction(Duration::from_secs(10))
+}
+
+struct OutputRecord {
+    topic: String,
+    key: Vec<u8>,
+    payload: Vec<u8>,
+    is_game: bool,
+}
+
+// runs on a blocking thread. Produces either the game or its parser errors.
+fn parse_message(payload: &[u8], to_topic: &str, source: &(String, i32, i64)) -> Vec<OutputRecord> {
+    let (pgn, result) = match RawChessGame::decode(payload) {
+        Ok(raw_game) => {
+            let pgn = format!("{}\n\n{}", raw_game.metadata, raw_game.moves);
+            let result = parse_game(&pgn);
+            (pgn, result)
+        },
+        Err(err) => (String::new(), Err(vec![GameParseError::InvalidPgn { reason: format!("failed to decode raw game: {}", err) }])),
+    };
+
+    match result {
+        Ok(game) => vec![OutputRecord {
+            topic: topic_for_variant(to_topic, game.variant().into()),
+            key: game.game_id.clone().into_bytes(),
+            payload: game.encode_to_vec(),
+            is_game: true,
+        }],
+        Err(errors) => {
+            error!("Failed to read game: {:?} for pgn: {}", errors, pgn);
+            let (source_topic, source_partition, source_offset) = source;
+            errors.into_iter()
+                .map(|error| OutputRecord {
+                    topic: TOPIC_CHESS_GAME_PARSER_ERRORS.to_owned(),
+                    key: format!("{}:{}:{}", source_topic, source_partition, source_offset).into_bytes(),
+                    payload: error.into_message(&pgn, source_topic, *source_partition, *source_offset).encode_to_vec(),
+                    is_game: false,
+                })
+                .collect()
+        },
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum GameParseError {
+    InvalidHeaderValue { header: String, value: String, reason: String },
+    InvalidComment { comment: String, reason: String },
+    UnknownCommentCommand { command: String, value: String },
+    UnknownNag { nag: u8 },
+    InvalidPgn { reason: String },
+    MissingField { reason: String },
+}
+
+// game id is taken from lichess link, games without it get a content hash
+pub fn parse_game(pgn: &str) -> Result<ChessGame, Vec<GameParseError>> {
+    let mut reader = BufferedReader::new(pgn.as_bytes());
+    let mut visitor = GameVisitor::new();
 
-        let task_future = tokio::spawn(message_future);
-        message_join_handles.push_back(task_future);
+    let mut game = match reader.read_game(&mut visitor) {
+        Ok(Some(result)) => result?,
+        Ok(None) => return Err(vec![GameParseError::InvalidPgn { reason: "no game found".to_owned() }]),
+        Err(err) => return Err(vec![GameParseError::InvalidPgn { reason: err.to_string() }]),
+    };
 
-        while message_join_handles.len() >= 16 {
-            message_join_handles.pop_front().unwrap().await.unwrap();
+    if game.game_id.is_empty() {
+        game.game_id = game_id_from_content(pgn);
+    }
+
+    Ok(game)
+}
+
+impl GameParseError {
+    fn invalid_header_value(header: &str, value: &str, reason: impl fmt::Display) -> Self {
+        Self::InvalidHeaderValue {
+            header: header.to_owned(),
+            value: value.to_owned(),
+            reason: reason.to_string(),
         }
+    }
 
-        time_io += (Instant::now() - io_started_at).as_secs_f64();
+    pub fn kind(&self) -> GameParserErrorKind {
+        match self {
+            Self::InvalidHeaderValue { .. } => GameParserErrorKind::InvalidHeaderValue,
+            Self::InvalidComment { .. } => GameParserErrorKind::InvalidComment,
+            Self::UnknownCommentCommand { .. } => GameParserErrorKind::UnknownCommentCommand,
+            Self::UnknownNag { .. } => GameParserErrorKind::UnknownNag,
+            Self::InvalidPgn { .. } => GameParserErrorKind::InvalidPgn,
+            Self::MissingField { .. } => GameParserErrorKind::MissingField,
+        }
+    }
 
-        let commit_message_started_at = Instant::now();
-        consumer.commit_message(&msg, CommitMode::Async).unwrap();
-        time_commit_offsets += (Instant::now() - commit_message_started_at).as_secs_f64();
+    pub fn into_message(self, raw_pgn: &str, source_topic: &str, source_partition: i32, source_offset: i64) -> GameParserError {
+        let message = self.to_string();
+        let kind = self.kind();
+
+        let (header, value) = match self {
+            Self::InvalidHeaderValue { header, value, .. } => (header, value),
+            Self::InvalidComment { comment, .. } => (String::new(), comment),
+            Self::UnknownCommentCommand { command, value } => (command, value),
+            Self::UnknownNag { nag } => (String::new(), nag.to_string()),
+            Self::InvalidPgn { .. } | Self::MissingField { .. } => (String::new(), String::new()),
+        };
 
-        if progress.update() {
-            info!("time_total: {}", time_total.round());
-            info!("time_message_recv: {}", time_message_recv.round());
-            info!("time_encode_decode: {}", time_encode_decode.round());
-            info!("time_io: {}", time_io.round());
-            info!("time_commit_offsets: {}", time_commit_offsets.round());
+        GameParserError {
+            kind: kind.into(),
+            header,
+            value,
+            message,
+            raw_pgn: raw_pgn.to_owned(),
+            source_topic: source_topic.to_owned(),
+            source_partition,
+            source_offset,
         }
+    }
+}
 
-        time_total += (Instant::now() - started_at).as_secs_f64();
+impl fmt::Display for GameParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHeaderValue { header, value, reason } => write!(f, "Invalid value for {} header: \"{}\": {}", header, value, reason),
+            Self::InvalidComment { comment, reason } => write!(f, "Invalid comment: \"{}\": {}", comment, reason),
+            Self::UnknownCommentCommand { command, value } => write!(f, "Unexpected comment: \"{}\"=\"{}\"", command, value),
+            Self::UnknownNag { nag } => write!(f, "Unexpected nag: {}", nag),
+            Self::InvalidPgn { reason } => write!(f, "Invalid pgn: {}", reason),
+            Self::MissingField { reason } => write!(f, "Missing field: {}", reason),
+        }
     }
 }
 
 struct GameVisitor {
     game: ChessGameBuilder,
-    errors: Vec<String>,
+    errors: Vec<GameParseError>,
 
     white_player: PlayerBuilder,
     black_player: PlayerBuilder,
@@ -128,6 +308,18 @@ struct GameVisitor {
     date: Option<NaiveDate>,
     used_utc_header_for_date: bool, // there are multiple date fields, we try to prefer utc one
 
+    variant: Variant,
+    starting_fen: Option<String>,
+    extra_headers: HashMap<String, String>,
+    // speed named in Event header can be outdated, as lichess changed speed limits over time
+    event_speed: Option<LichessSpeed>,
+    timecontrol_speed: Option<LichessSpeed>,
+    // game is replayed on the board to know actual squares of each move. None after a move which cannot be replayed
+    // (games are kept, with board moves only for the moves before it).
+    position: Option<VariantPosition>,
+    castling_mode: CastlingMode,
+    ply: u32,
+
     game_entries: Vec<GameEntry>,
 }
 
@@ -143,98 +335,163 @@ impl GameVisitor {
             date: None,
             used_utc_header_for_date: false,
 
+            variant: Variant::Standard,
+            starting_fen: None,
+            extra_headers: HashMap::new(),
+            event_speed: None,
+            timecontrol_speed: None,
+            position: None,
+            castling_mode: CastlingMode::Standard,
+            ply: 0,
+
             game_entries: Vec::new(),
         }
     }
+
+    fn parse_header_value<T>(&mut self, key: &str, value: &str) -> Option<T> where T: FromStr, T::Err: fmt::Display {
+        match value.parse() {
+            Ok(v) => Some(v),
+            Err(err) => {
+                self.errors.push(GameParseError::invalid_header_value(key, value, err));
+                None
+            },
+        }
+    }
+
+    fn parse_header_date(&mut self, key: &str, value: &str) -> Option<NaiveDate> {
+        match NaiveDate::parse_from_str(value, "%Y.%m.%d") {
+            Ok(v) => Some(v),
+            Err(err) => {
+                self.errors.push(GameParseError::invalid_header_value(key, value, err));
+                None
+            },
+        }
+    }
 }
 
 impl Visitor for GameVisitor {
-    type Result = Result<ChessGame, Vec<String>>;
+    type Result = Result<ChessGame, Vec<GameParseError>>;
 
     fn header(&mut self, key: &[u8], value: pgn_reader::RawHeader<'_>) {
         let key = String::from_utf8_lossy(key).to_string();
-        let value = value.decode_utf8().unwrap();
+        let value = match value.decode_utf8() {
+            Ok(v) => v,
+            Err(err) => {
+                self.errors.push(GameParseError::invalid_header_value(&key, &value.decode_utf8_lossy(), err));
+                return;
+            },
+        };
 
         match key.as_str() {
             "Event" => {
+                let event = LichessEvent::parse(&value);
+                self.game.rated(event.rated);
+                self.game.tournament_id(event.tournament_id.unwrap_or_default());
+                self.game.swiss_id(event.swiss_id.unwrap_or_default());
+                self.event_speed = event.speed;
                 self.game.event_name(value.to_string());
             },
             "Site" => {
+                if let Some(game_id) = game_id_from_link(&value) {
+                    self.game.game_id(game_id);
+                }
                 self.game.link(value.to_string());
             },
             "Date" => {
                 if !self.used_utc_header_for_date {
-                    self.date = Some(NaiveDate::parse_from_str(&value, "%Y.%m.%d").unwrap());
+                    if let Some(date) = self.parse_header_date(&key, &value) {
+                        self.date = Some(date);
+                    }
                 }
             },
             "Round" => {
-                if value == "-" {
-                    // ignore
-                } else {
-                    panic!("Unexpected value for round header: {}", value);
+                // lichess games do not have rounds
+                if value != "-" {
+                    self.extra_headers.insert(key, value.into_owned());
                 }
             },
             "White" => {
                 self.white_player.name(value.to_string());
             },
             "WhiteElo" => {
-                self.white_player.elo(value.parse().unwrap());
+                if let Some(elo) = self.parse_header_value(&key, &value) {
+                    self.white_player.elo(elo);
+                }
             },
             "WhiteTitle" => {
                 match PlayerTitle::try_from(value.as_ref()) {
                     Ok(title) => {
                         self.white_player.title(Some(title.into()));
                     },
-                    Err(err) => self.errors.push(err),
+                    Err(err) => self.errors.push(GameParseError::invalid_header_value(&key, &value, err)),
                 }
             },
             "Black" => {
                 self.black_player.name(value.to_string());
             },
             "BlackElo" => {
-                self.black_player.elo(value.parse().unwrap());
+                if let Some(elo) = self.parse_header_value(&key, &value) {
+                    self.black_player.elo(elo);
+                }
             },
             "BlackTitle" => {
                 match PlayerTitle::try_from(value.as_ref()) {
                     Ok(title) => {
                         self.black_player.title(Some(title.into()));
                     },
-                    Err(err) => self.errors.push(err),
+                    Err(err) => self.errors.push(GameParseError::invalid_header_value(&key, &value, err)),
                 }
             }
             "Result" => {
-                self.game.result(match value.as_ref() {
+                let result = match value.as_ref() {
                     "1-0" => GameResult::WhiteWins,
                     "0-1" => GameResult::BlackWins,
                     "1/2-1/2" => GameResult::Draw,
                     "*" => GameResult::Star,
-                    other => panic!("Unexpected result: {}", other),
-                }.into());
+                    _ => {
+                        self.errors.push(GameParseError::invalid_header_value(&key, &value, "unexpected result"));
+                        return;
+                    },
+                };
+                self.game.result(result.into());
             },
             "UTCDate" => {
-                self.date = Some(NaiveDate::parse_from_str(&value, "%Y.%m.%d").unwrap());
-                self.used_utc_header_for_date = true;
+                if let Some(date) = self.parse_header_date(&key, &value) {
+                    self.date = Some(date);
+                    self.used_utc_header_for_date = true;
+                }
             },
             "UTCTime" => {
-                if !self.used_utc_header_for_date {
-                    panic!("Expected UTCDate header to be parsed before the UTCTime");
-                }
+                let date = match (self.used_utc_header_for_date, self.date) {
+                    (true, Some(date)) => date,
+                    _ => {
+                        self.errors.push(GameParseError::invalid_header_value(&key, &value, "expected UTCDate header to be parsed before the UTCTime"));
+                        return;
+                    },
+                };
 
-                let datetime = NaiveDateTime::new(
-                    self.date.as_ref().unwrap().clone(),
-                    NaiveTime::parse_from_str(&value, "%H:%M:%S").unwrap()
-                );
+                let time = match NaiveTime::parse_from_str(&value, "%H:%M:%S") {
+                    Ok(v) => v,
+                    Err(err) => {
+                        self.errors.push(GameParseError::invalid_header_value(&key, &value, err));
+                        return;
+                    },
+                };
 
                 self.game.date(Some(Timestamp {
-                    seconds: datetime.timestamp(),
+                    seconds: NaiveDateTime::new(date, time).timestamp(),
                     nanos: 0,
                 }));
             },
             "WhiteRatingDiff" => {
-                self.game.rating_outcome_for_white(Some(value.parse().unwrap()));
+                if let Some(diff) = self.parse_header_value(&key, &value) {
+                    self.game.rating_outcome_for_white(Some(diff));
+                }
             },
             "BlackRatingDiff" => {
-                self.game.rating_outcome_for_black(Some(value.parse().unwrap()));
+                if let Some(diff) = self.parse_header_value(&key, &value) {
+                    self.game.rating_outcome_for_black(Some(diff));
+                }
             },
             "ECO" => {
                 self.game.eco(value.into_owned());
@@ -245,15 +502,30 @@ impl Visitor for GameVisitor {
             "TimeControl" => {
                 if value == "-" {
                     self.game.timecontrol(None);
-                } else if let Some(plus_index) = value.find("+") {
-                    let duration = value[0..plus_index].parse().unwrap();
-                    let increment = value[plus_index+1..].parse().unwrap();
-                    self.game.timecontrol(Some(Timecontrol {
-                        duration,
-                        increment,
-                    }));
+                    self.timecontrol_speed = Some(LichessSpeed::Correspondence);
+                } else if let Some((duration, increment)) = value.split_once('+') {
+                    let duration = self.parse_header_value(&key, duration);
+                    let increment = self.parse_header_value(&key, increment);
+                    if let (Some(duration), Some(increment)) = (duration, increment) {
+                        let timecontrol = Timecontrol {
+                            duration,
+                            increment,
+                        };
+                        self.timecontrol_speed = Some(LichessSpeed::from_game_timecontrol(Some(&timecontrol)));
+                        self.game.timecontrol(Some(timecontrol));
+                    }
                 } else {
-                    panic!("Expected timecontrol to contain plus or be set to \"-\" (which we interprete as None): {}", value);
+                    // "-" is interpreted as None
+                    self.errors.push(GameParseError::invalid_header_value(&key, &value, "expected timecontrol to contain plus or be set to \"-\""));
+                }
+            },
+            "Variant" => {
+                match Variant::try_from(value.as_ref()) {
+                    Ok(variant) => {
+                        self.variant = variant;
+                        self.game.variant(variant.into());
+                    },
+                    Err(err) => self.errors.push(GameParseError::invalid_header_value(&key, &value, err)),
                 }
             },
             "Termination" => {
@@ -263,8 +535,8 @@ impl Visitor for GameVisitor {
                     "Abandoned" => Some(Termination::Abandonded),
                     "Unterminated" => Some(Termination::Unterminated),
                     "Rules infraction" => Some(Termination::RulesInfraction),
-                    other => {
-                        self.errors.push(format!("Unexpected termination: {}", other));
+                    _ => {
+                        self.errors.push(GameParseError::invalid_header_value(&key, &value, "unexpected termination"));
                         None
                     },
                 };
@@ -273,13 +545,74 @@ impl Visitor for GameVisitor {
                     self.game.termination(termination.into());
                 }
             }
-            other => {
-                self.errors.push(format!("Unexpected header: {} = {}", other, value));
+            "SetUp" => {
+                // FEN header is enough to know that game starts from a custom position
+            },
+            "FEN" => {
+                self.starting_fen = Some(value.to_string());
+                self.game.starting_fen(value.into_owned());
             },
+            "Annotator" => {
+                self.game.annotator(value.into_owned());
+            },
+            "WhiteFideId" => {
+                if let Some(fide_id) = self.parse_header_value(&key, &value) {
+                    self.white_player.fide_id(Some(fide_id));
+                }
+            },
+            "BlackFideId" => {
+                if let Some(fide_id) = self.parse_header_value(&key, &value) {
+                    self.black_player.fide_id(Some(fide_id));
+                }
+            },
+            _ => {
+                self.extra_headers.insert(key, value.into_owned());
+            },
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        let speed = self.timecontrol_speed.or(self.event_speed);
+        self.game.speed(speed.map(|v| Speed::from(v).into()));
+
+        self.castling_mode = if self.variant == Variant::Chess960 {
+            CastlingMode::Chess960
+        } else {
+            CastlingMode::Standard
+        };
+
+        self.position = match self.starting_fen.as_ref() {
+            Some(fen) => match starting_position(fen, self.variant, self.castling_mode) {
+                Ok(position) => Some(position),
+                Err(reason) => {
+                    self.errors.push(GameParseError::invalid_header_value("FEN", fen, reason));
+                    None
+                },
+            },
+            None => Some(VariantPosition::new(shakmaty_variant(self.variant))),
+        };
+        if let Some(position) = self.position.as_ref() {
+            self.game.starting_position_hash(position_hash(position));
         }
+
+        Skip(false)
     }
 
     fn san(&mut self, san_plus: SanPlus) {
+        let board_move = match self.position.as_mut().map(|position| replay_move(position, &san_plus)) {
+            Some(Ok((m, san, fen_after))) => {
+                self.ply += 1;
+                let position_hash = self.position.as_ref().map(position_hash).unwrap_or(0);
+                Some(into_board_move(&m, san, fen_after, position_hash, self.ply, self.castling_mode))
+            },
+            Some(Err(reason)) => {
+                warn!("failed to replay move {} at ply {}, leaving the following moves without board moves: {}", san_plus, self.ply + 1, reason);
+                self.position = None;
+                None
+            },
+            None => None,
+        };
+
         let is_check = san_plus.suffix.map(|v| v == Suffix::Check);
         let is_checkmate = san_plus.suffix.map(|v| v == Suffix::Checkmate);
 
@@ -331,51 +664,77 @@ impl Visitor for GameVisitor {
             san,
             nag: None,
             comment: None,
+            board_move,
         });
     }
 
     fn nag(&mut self, nag: pgn_reader::Nag) {
-        self.game_entries.push(GameEntry {
-            san: None,
-            nag: Some(Nag::from(nag).into()),
-            comment: None,
-        });
+        match Nag::try_from(nag) {
+            Ok(nag) => self.game_entries.push(GameEntry {
+                san: None,
+                nag: Some(nag.into()),
+                comment: None,
+                board_move: None,
+            }),
+            Err(nag) => self.errors.push(GameParseError::UnknownNag { nag }),
+        }
     }
 
     fn comment(&mut self, comment: RawComment<'_>) {
-        let mut comment: String = String::from_utf8(comment.as_bytes().to_vec()).unwrap();
+        let mut comment: String = match String::from_utf8(comment.as_bytes().to_vec()) {
+            Ok(v) => v,
+            Err(err) => {
+                self.errors.push(GameParseError::InvalidComment {
+                    comment: String::from_utf8_lossy(comment.as_bytes()).to_string(),
+                    reason: err.to_string(),
+                });
+                return;
+            },
+        };
+
         while let Some(comment_begin) = comment.find("[") {
-            let comment_end = comment.find("]").unwrap();
+            let comment_end = match comment[comment_begin..].find("]") {
+                Some(v) => comment_begin + v,
+                None => {
+                    self.errors.push(GameParseError::InvalidComment { comment, reason: "command is not closed".to_owned() });
+                    return;
+                },
+            };
             let part = comment[comment_begin+1..comment_end].to_owned();
             comment = comment[comment_end+1..].to_owned();
 
-            let s = part.find(" ").unwrap();
-            let key = part[0..s].to_owned();
-            let value = part[s+1..].to_owned();
+            let (key, value) = match part.split_once(" ") {
+                Some(v) => v,
+                None => {
+                    self.errors.push(GameParseError::InvalidComment { comment: part.clone(), reason: "command without value".to_owned() });
+                    continue;
+                },
+            };
 
             let mut clock = None;
             let mut eval = None;
             let mut getting_mated_in = None;
 
-            match key.as_ref() {
-                "%clk" => {
-                    clock = Some(NaiveTime::parse_from_str(&value, "%H:%M:%S").unwrap().num_seconds_from_midnight());
+            let parsed = match key {
+                "%clk" => NaiveTime::parse_from_str(value, "%H:%M:%S")
+                    .map(|v| clock = Some(v.num_seconds_from_midnight()))
+                    .map_err(|err| err.to_string()),
+                "%eval" => if let Some(mate) = value.strip_prefix("#") {
+                    mate.parse().map(|v| getting_mated_in = Some(v)).map_err(|err: std::num::ParseIntError| err.to_string())
+                } else {
+                    value.parse().map(|v| eval = Some(v)).map_err(|err: std::num::ParseFloatError| err.to_string())
                 },
-                "%eval" => {
-                    if value.starts_with("#") {
-                        getting_mated_in = Some(value[1..].parse().unwrap());
-                    } else {
-                        eval = match value.parse() {
-                            Ok(v) => Some(v),
-                            Err(err) => {
-                                panic!("failed to parse eval value = \"{}\": {:?}, whole part is: \"{}\"", value, err, part);
-                            },
-                        }
-                    }
+                other => {
+                    self.errors.push(GameParseError::UnknownCommentCommand { command: other.to_owned(), value: value.to_owned() });
+                    continue;
                 },
-                other => panic!("Unexpected comment: \"{}\"=\"{}\"", other, value),
             };
 
+            if let Err(reason) = parsed {
+                self.errors.push(GameParseError::InvalidComment { comment: part.clone(), reason });
+                continue;
+            }
+
             self.game_entries.push(GameEntry {
                 san: None,
                 nag: None,
@@ -384,6 +743,7 @@ impl Visitor for GameVisitor {
                     eval,
                     getting_mated_in,
                 }),
+                board_move: None,
             });
         }
     }
@@ -393,15 +753,63 @@ impl Visitor for GameVisitor {
             return Err(self.errors.clone());
         }
 
-        self.game.white_player(Some(self.white_player.build().unwrap()));
-        self.game.black_player(Some(self.black_player.build().unwrap()));
+        let white_player = self.white_player.build()
+            .map_err(|err| vec![GameParseError::MissingField { reason: format!("white player: {}", err) }])?;
+        let black_player = self.black_player.build()
+            .map_err(|err| vec![GameParseError::MissingField { reason: format!("black player: {}", err) }])?;
+
+        self.game.white_player(Some(white_player));
+        self.game.black_player(Some(black_player));
         self.game.game_entries(self.game_entries.clone());
-        self.game.build().map_err(|v| vec![v.to_string()])
+        self.game.extra_headers(self.extra_headers.clone());
+        self.game.build().map_err(|err| vec![GameParseError::MissingField { reason: err.to_string() }])
+    }
+}
+
+// returns the move, its san with check suffix and fen of the position after it
+fn replay_move(position: &mut VariantPosition, san_plus: &SanPlus) -> Result<(Move, String, String), String> {
+    let m = san_plus.san.to_move(position).map_err(|err| err.to_string())?;
+    let san = SanPlus::from_move_and_play_unchecked(position, &m).to_string();
+    let fen_after = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+    Ok((m, san, fen_after))
+}
+
+fn into_board_move(m: &Move, san: String, fen_after: String, position_hash: i64, ply: u32, castling_mode: CastlingMode) -> BoardMove {
+    // squares are taken from standard uci, so that king destination is used for castling even in chess960
+    let (from, to) = match m.to_uci(CastlingMode::Standard) {
+        Uci::Normal { from, to, .. } => (Some(from), to),
+        Uci::Put { to, .. } => (None, to),
+        Uci::Null => (None, m.to()),
+    };
+
+    BoardMove {
+        ply,
+        role: Role::from(m.role()).into(),
+        from: from.map(Square::from),
+        to: Some(Square::from(to)),
+        promotion: m.promotion().map(|v| Role::from(v).into()),
+        capture: m.is_capture(),
+        uci: m.to_uci(castling_mode).to_string(),
+        san,
+        fen_after,
+        position_hash,
     }
 }
 
-fn random_game_key() -> Vec<u8> {
-    let mut id = [0u8; 12];
-    rand::thread_rng().fill(&mut id);
-    id.to_vec()
-}
\ No newline at end of file
+fn starting_position(fen: &str, variant: Variant, castling_mode: CastlingMode) -> Result<VariantPosition, String> {
+    let fen: Fen = fen.parse().map_err(|err: ParseFenError| err.to_string())?;
+    VariantPosition::from_setup(shakmaty_variant(variant), fen.into_setup(), castling_mode).map_err(|err| err.to_string())
+}
+
+fn shakmaty_variant(variant: Variant) -> ShakmatyVariant {
+    match variant {
+        Variant::Standard | Variant::Chess960 => ShakmatyVariant::Chess,
+        Variant::Crazyhouse => ShakmatyVariant::Crazyhouse,
+        Variant::Antichess => ShakmatyVariant::Antichess,
+        Variant::Atomic => ShakmatyVariant::Atomic,
+        Variant::Horde => ShakmatyVariant::Horde,
+        Variant::KingOfTheHill => ShakmatyVariant::KingOfTheHill,
+        Variant::RacingKings => ShakmatyVariant::RacingKings,
+        Variant::ThreeCheck => ShakmatyVariant::ThreeCheck,
+    }
+}
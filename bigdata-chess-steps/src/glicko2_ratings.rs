@@ -0,0 +1,404 @@
+use {
+    std::{sync::Arc, path::Path, collections::{BTreeMap, HashMap, HashSet}, time::Duration},
+    tracing::{info, warn, error},
+    rdkafka::{
+        consumer::{Consumer, StreamConsumer},
+        producer::{FutureRecord, FutureProducer, Producer},
+        topic_partition_list::{TopicPartitionList, Offset},
+        error::KafkaResult,
+        Message,
+    },
+    prost::Message as ProstMessage,
+    tokio::time::{timeout, sleep},
+    leveldb::{
+        database::Database,
+        batch::{Batch, Writebatch},
+        kv::KV,
+        options::{Options, ReadOptions, WriteOptions},
+    },
+    bigdata_chess_core::{
+        config::Glicko2RatingsStepConfig,
+        queue::{Queue, StreamingContext},
+        storage::{Storage, GAME_DATA_RATINGS},
+        entity::into_chess_game_rating_entities,
+        glicko2::Glicko2Rating,
+        lichess::LichessSpeed,
+        data::{ChessGame, GameResult, GameRatings, PlayerRatingChange, Speed},
+    },
+    crate::{
+        byte_key::ByteKey,
+        progress::Progress,
+        data_file::write_csv_data_file,
+    },
+};
+
+const SECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0;
+
+// pending games are drained when no new games arrive for this long, so that the last games of a data file are rated
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+const OUTBOX_RATINGS_KEY: &[u8] = b"outbox\0ratings";
+const OUTBOX_OFFSETS_KEY: &[u8] = b"outbox\0offsets";
+
+// ratings of players are separate for each speed, like in lichess. Ids of rated games are kept too, so that games
+// received again after restart are not rated twice. Changes are kept in memory until ratings of the games are uploaded,
+// so that games which are received again after a crash are rated again instead of being skipped. Ratings of the games
+// are written together with the changes into an outbox, which is kept until they are sent to the topic.
+struct RatingStore {
+    database: Database<ByteKey>,
+    pending_players: HashMap<Vec<u8>, PlayerState>,
+    pending_games: HashSet<Vec<u8>>,
+}
+
+// ratings which are not known to be in the topic yet, with offsets of the input topic to commit together with them
+struct Outbox {
+    ratings: Vec<GameRatings>,
+    offsets: Vec<(i32, i64)>,
+}
+
+#[derive(Default, Clone)]
+struct PlayerState {
+    rating: Glicko2Rating,
+    last_played_at: Option<i64>,
+}
+
+// recomputes ratings of players with glicko-2, processing games in the order they were played
+pub async fn glicko2_ratings_step(config: &Glicko2RatingsStepConfig, queue: Arc<Queue>, storage: Arc<Storage>) -> std::io::Result<()> {
+    let variant = config.variant();
+    info!("running glicko2 ratings step for {} games", variant.name());
+
+    let from_topic = config.from_topic();
+    let consumer = queue.consumer_with_manual_commit(&config.group_id(), config.max_poll_interval());
+    consumer.subscribe(&vec![from_topic.as_str()]).unwrap();
+    let producer = queue.transactional_producer(&config.transactional_id());
+
+    let mut store = RatingStore::open(&config.database_path());
+    if let Some(outbox) = store.outbox() {
+        // offsets of a partition with rated games always move forward, so outbox with all its offsets committed was sent
+        let mut partitions = TopicPartitionList::new();
+        for (partition, _) in &outbox.offsets {
+            partitions.add_partition(&from_topic, *partition);
+        }
+        let committed = consumer.committed_offsets(partitions, Duration::from_secs(10)).unwrap();
+        let sent = outbox.offsets.iter().all(|(partition, offset)| {
+            match committed.find_partition(&from_topic, *partition).map(|v| v.offset()) {
+                Some(Offset::Offset(committed_offset)) => committed_offset >= *offset,
+                _ => false,
+            }
+        });
+
+        if !sent {
+            info!("sending {} ratings left from previous run", outbox.ratings.len());
+            send_outbox_with_retries(&producer, &consumer, config, &outbox).await?;
+        }
+        store.clear_outbox();
+    }
+
+    let reorder_window = config.reorder_window().as_secs() as i64;
+    let mut progress = Progress::new("rated games".to_owned());
+
+    // games waiting to be rated, by date, partition and offset
+    let mut pending: BTreeMap<(i64, i32, i64), ChessGame> = BTreeMap::new();
+    let mut consumed_offsets: HashMap<i32, i64> = HashMap::new();
+    let mut latest_date = i64::MIN;
+    let mut games_without_date = 0;
+
+    let mut ratings = Vec::new();
+    let mut emitted = Vec::new();
+    let mut games_since_flush = 0;
+
+    loop {
+        let msg = match timeout(IDLE_TIMEOUT, consumer.recv()).await {
+            Ok(msg) => Some(msg.unwrap()),
+            Err(_) => None,
+        };
+
+        let rate_until = match &msg {
+            Some(msg) => {
+                consumed_offsets.insert(msg.partition(), msg.offset());
+                let game = ChessGame::decode(msg.payload().unwrap()).unwrap();
+
+                // games without date cannot be ordered
+                match game.date.as_ref().map(|v| v.seconds) {
+                    Some(date) => {
+                        latest_date = latest_date.max(date);
+                        pending.insert((date, msg.partition(), msg.offset()), game);
+                    },
+                    None => {
+                        games_without_date += 1;
+                        warn!("skipping game {} without date ({} games without date so far)", game.game_id, games_without_date);
+                    },
+                }
+
+                latest_date.saturating_sub(reorder_window)
+            },
+            None => i64::MAX,
+        };
+
+        while let Some(key) = pending.keys().next().cloned().filter(|(date, _, _)| *date <= rate_until) {
+            let game = pending.remove(&key).unwrap();
+            games_since_flush += 1;
+            progress.update();
+
+            let game_ratings = match store.rate_game(&game) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            ratings.append(&mut into_chess_game_rating_entities(&game_ratings));
+            emitted.push(game_ratings);
+        }
+
+        if games_since_flush >= config.flush_games() || (msg.is_none() && games_since_flush > 0) {
+            write_csv_data_file(&storage, variant, GAME_DATA_RATINGS, &ratings).await;
+            ratings.clear();
+
+            // games which are still pending are received again after restart
+            let offsets = consumed_offsets.iter()
+                .map(|(partition, offset)| {
+                    let commit_offset = pending.keys()
+                        .filter(|(_, pending_partition, _)| pending_partition == partition)
+                        .map(|(_, _, pending_offset)| *pending_offset)
+                        .min()
+                        .unwrap_or(offset + 1);
+                    (*partition, commit_offset)
+                })
+                .collect();
+
+            // crash before this point rates the games again, producing the same rows for them once more. After it,
+            // ratings are sent from the outbox on restart, unless their transaction was committed.
+            let outbox = Outbox { ratings: std::mem::take(&mut emitted), offsets };
+            store.flush(&outbox);
+            send_outbox_with_retries(&producer, &consumer, config, &outbox).await?;
+            store.clear_outbox();
+
+            games_since_flush = 0;
+        }
+    }
+}
+
+impl RatingStore {
+    fn open(path: &str) -> Self {
+        let mut options = Options::new();
+        options.create_if_missing = true;
+
+        Self {
+            database: Database::open(Path::new(path), options).unwrap(),
+            pending_players: HashMap::new(),
+            pending_games: HashSet::new(),
+        }
+    }
+
+    fn flush(&mut self, outbox: &Outbox) {
+        let mut batch = Writebatch::new();
+        for (key, state) in self.pending_players.drain() {
+            batch.put(ByteKey(key), &state.to_bytes());
+        }
+        for key in self.pending_games.drain() {
+            batch.put(ByteKey(key), &[]);
+        }
+        batch.put(ByteKey(OUTBOX_RATINGS_KEY.to_vec()), &outbox.ratings_to_bytes());
+        batch.put(ByteKey(OUTBOX_OFFSETS_KEY.to_vec()), &outbox.offsets_to_bytes());
+        self.database.write(WriteOptions::new(), &batch).unwrap();
+    }
+
+    fn outbox(&self) -> Option<Outbox> {
+        let ratings = self.database.get(ReadOptions::new(), ByteKey(OUTBOX_RATINGS_KEY.to_vec())).unwrap()?;
+        let offsets = self.database.get(ReadOptions::new(), ByteKey(OUTBOX_OFFSETS_KEY.to_vec())).unwrap()?;
+        Some(Outbox::from_bytes(&ratings, &offsets))
+    }
+
+    fn clear_outbox(&mut self) {
+        let mut batch = Writebatch::new();
+        batch.delete(ByteKey(OUTBOX_RATINGS_KEY.to_vec()));
+        batch.delete(ByteKey(OUTBOX_OFFSETS_KEY.to_vec()));
+        self.database.write(WriteOptions::new(), &batch).unwrap();
+    }
+
+    // casual games, unfinished games, games parsed before game ids were introduced and games which were already rated
+    // are skipped
+    fn rate_game(&mut self, game: &ChessGame) -> Option<GameRatings> {
+        if game.rated == Some(false) || game.game_id.is_empty() {
+            return None;
+        }
+
+        let white_score = match game.result() {
+            GameResult::WhiteWins => 1.0,
+            GameResult::BlackWins => 0.0,
+            GameResult::Draw => 0.5,
+            GameResult::Star => return None,
+        };
+
+        let game_key = rated_game_key(&game.game_id);
+        if self.pending_games.contains(&game_key) || self.database.get(ReadOptions::new(), ByteKey(game_key.clone())).unwrap().is_some() {
+            return None;
+        }
+
+        let white = game.white_player.as_ref()?;
+        let black = game.black_player.as_ref()?;
+        let date = game.date.as_ref()?.seconds;
+        // games parsed before speed was introduced only have timecontrol
+        let speed = game.speed.and_then(Speed::from_i32)
+            .map(LichessSpeed::from)
+            .unwrap_or_else(|| LichessSpeed::from_game_timecontrol(game.timecontrol.as_ref()));
+
+        let white_before = self.player(speed, &white.name).rating_at(date);
+        let black_before = self.player(speed, &black.name).rating_at(date);
+        let white_after = white_before.after_game(&black_before, white_score);
+        let black_after = black_before.after_game(&white_before, 1.0 - white_score);
+
+        self.pending_players.insert(player_key(speed, &white.name), PlayerState { rating: white_after, last_played_at: Some(date) });
+        self.pending_players.insert(player_key(speed, &black.name), PlayerState { rating: black_after, last_played_at: Some(date) });
+        self.pending_games.insert(game_key);
+
+        Some(GameRatings {
+            game_id: game.game_id.clone(),
+            speed: Speed::from(speed).into(),
+            date: game.date.clone(),
+            white: Some(rating_change(&white.name, &white_before, &white_after, white.elo, game.rating_outcome_for_white)),
+            black: Some(rating_change(&black.name, &black_before, &black_after, black.elo, game.rating_outcome_for_black)),
+        })
+    }
+
+    fn player(&self, speed: LichessSpeed, name: &str) -> PlayerState {
+        let key = player_key(speed, name);
+        if let Some(state) = self.pending_players.get(&key) {
+            return state.clone();
+        }
+
+        self.database.get(ReadOptions::new(), ByteKey(key))
+            .unwrap()
+            .map(|v| PlayerState::from_bytes(&v))
+            .unwrap_or_default()
+    }
+}
+
+impl Outbox {
+    fn ratings_to_bytes(&self) -> Vec<u8> {
+        self.ratings.iter().flat_map(|v| v.encode_length_delimited_to_vec()).collect()
+    }
+
+    fn offsets_to_bytes(&self) -> Vec<u8> {
+        self.offsets.iter()
+            .flat_map(|(partition, offset)| partition.to_be_bytes().into_iter().chain(offset.to_be_bytes()))
+            .collect()
+    }
+
+    fn from_bytes(mut ratings: &[u8], offsets: &[u8]) -> Self {
+        let mut decoded_ratings = Vec::new();
+        while !ratings.is_empty() {
+            decoded_ratings.push(GameRatings::decode_length_delimited(&mut ratings).unwrap());
+        }
+
+        Self {
+            ratings: decoded_ratings,
+            offsets: offsets.chunks_exact(12)
+                .map(|v| (i32::from_be_bytes(v[0..4].try_into().unwrap()), i64::from_be_bytes(v[4..12].try_into().unwrap())))
+                .collect(),
+        }
+    }
+}
+
+impl PlayerState {
+    fn rating_at(&self, date: i64) -> Glicko2Rating {
+        match self.last_played_at {
+            Some(last_played_at) => self.rating.after_inactivity((date - last_played_at) as f64 / SECONDS_PER_DAY),
+            None => self.rating,
+        }
+    }
+
+    // only states of players who played are stored
+    fn to_bytes(&self) -> Vec<u8> {
+        [self.rating.rating, self.rating.deviation, self.rating.volatility]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .chain(self.last_played_at.unwrap_or_default().to_be_bytes())
+            .collect()
+    }
+
+    fn from_bytes(data: &[u8]) -> Self {
+        let value = |index: usize| -> [u8; 8] { data[index * 8..(index + 1) * 8].try_into().unwrap() };
+
+        Self {
+            rating: Glicko2Rating {
+                rating: f64::from_be_bytes(value(0)),
+                deviation: f64::from_be_bytes(value(1)),
+                volatility: f64::from_be_bytes(value(2)),
+            },
+            last_played_at: Some(i64::from_be_bytes(value(3))),
+        }
+    }
+}
+
+async fn send_outbox_with_retries(
+    producer: &FutureProducer,
+    consumer: &StreamConsumer<StreamingContext>,
+    config: &Glicko2RatingsStepConfig,
+    outbox: &Outbox,
+) -> std::io::Result<()> {
+    let send_retries = config.send_retries();
+
+    let mut attempt = 0;
+    while let Err(err) = send_outbox(producer, consumer, &config.from_topic(), &config.to_topic(), outbox).await {
+        if let Err(err) = producer.abort_transaction(Duration::from_secs(10)) {
+            error!("failed to abort transaction: {}", err);
+        }
+
+        if attempt >= send_retries {
+            return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("failed to send game ratings: {}", err)));
+        }
+        attempt += 1;
+        warn!("failed to send game ratings (attempt {}/{}): {}", attempt, send_retries, err);
+        sleep(Duration::from_secs(2u64.pow(attempt))).await;
+    }
+
+    Ok(())
+}
+
+async fn send_outbox(
+    producer: &FutureProducer,
+    consumer: &StreamConsumer<StreamingContext>,
+    from_topic: &str,
+    to_topic: &str,
+    outbox: &Outbox,
+) -> KafkaResult<()> {
+    producer.begin_transaction()?;
+
+    for game_ratings in &outbox.ratings {
+        producer.send(
+            FutureRecord::to(to_topic)
+                .payload(&game_ratings.encode_to_vec())
+                .key(&game_ratings.game_id),
+            Duration::from_secs(10)
+        ).await.map_err(|(err, _)| err)?;
+    }
+
+    let mut offsets = TopicPartitionList::new();
+    for (partition, offset) in &outbox.offsets {
+        offsets.add_partition_offset(from_topic, *partition, Offset::Offset(*offset))?;
+    }
+
+    producer.send_offsets_to_transaction(&offsets, &consumer.group_metadata().unwrap(), Duration::from_secs(10))?;
+    producer.commit_transaction(Duration::from_secs(10))
+}
+
+fn rating_change(name: &str, before: &Glicko2Rating, after: &Glicko2Rating, lichess_rating: u32, lichess_rating_diff: Option<i32>) -> PlayerRatingChange {
+    PlayerRatingChange {
+        name: name.to_owned(),
+        rating_before: before.rating,
+        rating_after: after.rating,
+        deviation_before: before.deviation,
+        deviation_after: after.deviation,
+        volatility_after: after.volatility,
+        lichess_rating,
+        lichess_rating_diff,
+    }
+}
+
+fn player_key(speed: LichessSpeed, name: &str) -> Vec<u8> {
+    format!("player\0{}\0{}", speed.name(), name).into_bytes()
+}
+
+fn rated_game_key(game_id: &str) -> Vec<u8> {
+    format!("game\0{}", game_id).into_bytes()
+}
@@ -1,11 +1,29 @@
 use {
     std::{sync::Arc, time::Duration, collections::HashSet},
     tracing::info,
-    bigdata_chess_core::storage::Storage,
+    bigdata_chess_core::storage::{
+        Storage,
+        GAME_DATA_GAMES,
+        GAME_DATA_MOVES,
+        GAME_DATA_COMMENTS_EVAL,
+        GAME_DATA_CLOCKS,
+        GAME_DATA_MOVE_JUDGEMENTS,
+        GAME_DATA_POSITIONS,
+        GAME_DATA_RATINGS,
+    },
     tokio::{time::sleep, fs, process::Command},
     bigdata_chess_core::config::HdfsImportStepConfig,
 };
 
+// kinds of game data files which are imported without limits, with tables they are loaded into
+const GAME_DATA_TABLES: [(&str, &str); 5] = [
+    (GAME_DATA_COMMENTS_EVAL, "chess_game_comments_eval"),
+    (GAME_DATA_CLOCKS, "chess_game_clocks"),
+    (GAME_DATA_MOVE_JUDGEMENTS, "chess_game_move_judgements"),
+    (GAME_DATA_POSITIONS, "chess_game_positions"),
+    (GAME_DATA_RATINGS, "chess_game_ratings"),
+];
+
 pub async fn hdfs_import_step(config: &HdfsImportStepConfig, storage: Arc<Storage>) {
     info!("running hdfs import step");
 
@@ -13,7 +31,7 @@ pub async fn hdfs_import_step(config: &HdfsImportStepConfig, storage: Arc<Storag
         let keys_in_local_storage = load_sync_state().await;
         let mut synced_keys = HashSet::new();
 
-        let games_in_remote_storage = storage.remote_list_game_data_files().await.unwrap();
+        let games_in_remote_storage = storage.remote_list_game_data_files(GAME_DATA_GAMES).await.unwrap();
         let mut total_games_synced = 0;
         for game_in_remote_storage in games_in_remote_storage {
             total_games_synced += 1;
@@ -44,7 +62,7 @@ pub async fn hdfs_import_step(config: &HdfsImportStepConfig, storage: Arc<Storag
             info!("total games synced: {}", total_games_synced);
         }
 
-        let game_moves_in_remote_storage = storage.remote_list_game_moves_files().await.unwrap();
+        let game_moves_in_remote_storage = storage.remote_list_game_data_files(GAME_DATA_MOVES).await.unwrap();
         let mut total_game_moves_synced = 0;
         for game_move_in_remote_storage in game_moves_in_remote_storage {
             total_game_moves_synced += 1;
@@ -75,23 +93,25 @@ pub async fn hdfs_import_step(config: &HdfsImportStepConfig, storage: Arc<Storag
             info!("total game moves synced: {}", total_game_moves_synced);
         }
 
-        let game_eval_comments_in_remote_storage = storage.remote_list_game_comment_eval_files().await.unwrap();
-        for eval_comment in game_eval_comments_in_remote_storage {
-            if !keys_in_local_storage.contains(&eval_comment) {
-                synced_keys.insert(eval_comment.clone());
+        for (kind, table_name) in GAME_DATA_TABLES {
+            let files_in_remote_storage = storage.remote_list_game_data_files(kind).await.unwrap();
+            for file in files_in_remote_storage {
+                if !keys_in_local_storage.contains(&file) {
+                    synced_keys.insert(file.clone());
 
-                let file_id = file_name_from_path(&eval_comment);
+                    let file_id = file_name_from_path(&file);
 
-                info!("syncing eval comments {}", file_id);
-                let eval_comment_data = storage.remote_game_data_file(&eval_comment).await.unwrap();
+                    info!("syncing {} {}", kind, file_id);
+                    let data = storage.remote_game_data_file(&file).await.unwrap();
 
-                import_data_into_table(&file_id, eval_comment_data, "chess_game_comments_eval").await;
-                
-                let mut all_keys = keys_in_local_storage.clone();
-                all_keys.extend(synced_keys.clone().into_iter());
-                save_sync_state(&all_keys).await;
-            } else {
-                info!("game eval comments already synced: {}", eval_comment);
+                    import_data_into_table(&file_id, data, table_name).await;
+
+                    let mut all_keys = keys_in_local_storage.clone();
+                    all_keys.extend(synced_keys.clone().into_iter());
+                    save_sync_state(&all_keys).await;
+                } else {
+                    info!("{} already synced: {}", kind, file);
+                }
             }
         }
 
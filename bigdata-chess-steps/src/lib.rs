@@ -1,9 +1,19 @@
+pub mod byte_key;
+pub mod chunk_reader;
 pub mod chunk_splitter;
+pub mod data_file;
 pub mod file_downloader;
 pub mod game_parser;
+pub mod glicko2_ratings;
 pub mod hdfs_import;
+pub mod move_judgement;
+pub mod opening_tree;
+pub mod pgn_splitter;
+pub mod position_index;
 pub mod postgres_import;
 pub mod progress;
+pub mod replay_parser_errors;
+pub mod runner;
 pub mod storage_import;
 pub mod update_checker;
 pub mod utils;
\ No newline at end of file
@@ -1,32 +1,156 @@
+mod byte_key;
+mod chunk_reader;
+mod chunk_splitter;
+mod data_file;
 mod file_downloader;
+mod game_parser;
+mod glicko2_ratings;
 mod hdfs_import;
+mod move_judgement;
+mod opening_tree;
+mod pgn_splitter;
+mod position_index;
 mod postgres_import;
 mod progress;
+mod replay_parser_errors;
+mod runner;
 mod storage_import;
 mod update_checker;
 mod utils;
 
 use {
-    std::sync::Arc,
-    tracing::{info, error},
-    bigdata_chess_core::{
-        config::Config,
-        storage::Storage,
-    },
+    std::{sync::Arc, process::exit},
+    tracing::error,
+    clap::{Parser, Subcommand},
+    bigdata_chess_core::{config::Config, lichess::LichessSpeed, position::position_hash_from_fen},
     crate::{
-        hdfs_import::hdfs_import_step,
+        opening_tree::{OpeningTree, position_key_for_query},
+        position_index::PositionIndex,
+        runner::{Step, run_steps, enabled_steps},
         utils::init_logging,
     },
 };
 
+#[derive(Parser)]
+#[command(about = "Runs bigdata-chess pipeline steps")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Path to config file (defaults to ./config.toml or /config/config.toml)
+    #[arg(long, global = true)]
+    config: Option<String>,
+
+    /// Override config value, for example: --set steps.game_parser.to_topic=chess-games-blue
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the given steps together on one runtime
+    Run {
+        #[arg(value_enum, required_unless_present = "all_enabled", conflicts_with = "all_enabled")]
+        steps: Vec<Step>,
+
+        /// Run every step which is enabled in config
+        #[arg(long)]
+        all_enabled: bool,
+    },
+    /// Print stats of moves played from a position in the opening tree
+    OpeningTree {
+        /// Position to look up, starting position by default
+        #[arg(long)]
+        fen: Option<String>,
+
+        /// Moves in san played from the position, for example: "e4 c5"
+        #[arg(long)]
+        moves: Option<String>,
+
+        /// Only games with average rating in the band starting with this, for example: 2000
+        #[arg(long)]
+        rating_band: Option<u32>,
+
+        /// Only games of this speed, for example: blitz
+        #[arg(long, value_parser = parse_speed)]
+        speed: Option<LichessSpeed>,
+    },
+    /// Print ids of games which reached a position, with plies at which they did
+    GamesByPosition {
+        /// Position to look up
+        #[arg(long)]
+        fen: String,
+
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+}
+
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     init_logging(None);
 
-    let config = Config::load();
-    let storage = Arc::new(Storage::new(&config.infra().storage()));
+    let cli = Cli::parse();
+    let config = match Config::load_with_overrides(cli.config.as_deref(), &cli.overrides) {
+        Ok(v) => Arc::new(v),
+        Err(err) => {
+            error!("failed to load config: {:#}", err);
+            exit(1);
+        },
+    };
 
-    hdfs_import_step(config.steps.hdfs_import(), storage).await;
+    match cli.command {
+        Command::Run { steps, all_enabled } => {
+            let steps = if all_enabled {
+                enabled_steps(&config.steps)
+            } else {
+                steps
+            };
+
+            run_steps(config, steps).await;
+        },
+        Command::OpeningTree { fen, moves, rating_band, speed } => {
+            let position_key = match position_key_for_query(fen.as_deref(), moves.as_deref()) {
+                Ok(v) => v,
+                Err(err) => {
+                    error!("failed to find position: {}", err);
+                    exit(1);
+                },
+            };
+
+            let tree = OpeningTree::open(&config.steps.opening_tree.database_path());
+            println!("{}", position_key);
+            for (san, stats) in tree.moves(&position_key, rating_band, speed) {
+                println!(
+                    "{:8} games: {:10} white: {:5.1}% draws: {:5.1}% black: {:5.1}% average elo: {}",
+                    san,
+                    stats.games,
+                    100.0 * stats.white_wins as f64 / stats.games as f64,
+                    100.0 * stats.draws as f64 / stats.games as f64,
+                    100.0 * stats.black_wins as f64 / stats.games as f64,
+                    stats.average_elo(),
+                );
+            }
+        },
+        Command::GamesByPosition { fen, limit } => {
+            let position_hash = match position_hash_from_fen(&fen) {
+                Ok(v) => v,
+                Err(err) => {
+                    error!("failed to find position: {}", err);
+                    exit(1);
+                },
+            };
+
+            let index = PositionIndex::open(&config.steps.position_index.database_path());
+            for (game_id, plies) in index.games(position_hash, limit) {
+                println!("{} {:?}", game_id, plies);
+            }
+        },
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+fn parse_speed(value: &str) -> Result<LichessSpeed, String> {
+    LichessSpeed::try_from(value)
+}
@@ -0,0 +1,147 @@
+use {
+    std::{sync::Arc, time::Duration, collections::HashMap},
+    tracing::{info, warn, error},
+    rdkafka::{
+        Message,
+        consumer::{Consumer, StreamConsumer},
+        producer::{FutureRecord, FutureProducer, Producer},
+        topic_partition_list::{TopicPartitionList, Offset},
+        error::KafkaResult,
+    },
+    prost::Message as ProstMessage,
+    tokio::time::{timeout, sleep},
+    bigdata_chess_core::{
+        config::MoveJudgementStepConfig,
+        queue::{Queue, StreamingContext},
+        storage::{Storage, GAME_DATA_MOVE_JUDGEMENTS},
+        entity::{ChessGameMoveJudgementEntity, into_chess_game_move_judgement_entities},
+        data::{ChessGame, GameJudgements, SideJudgements},
+    },
+    crate::{
+        progress::Progress,
+        data_file::write_csv_data_file,
+    },
+};
+
+// judgements are uploaded when no new games arrive for this long, so that the last games of the topic are not held back
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// classifies moves of games with evaluations as inaccuracies, mistakes and blunders
+pub async fn move_judgement_step(config: &MoveJudgementStepConfig, queue: Arc<Queue>, storage: Arc<Storage>) -> std::io::Result<()> {
+    let variant = config.variant();
+    info!("running move judgement step for {} games", variant.name());
+
+    let consumer = queue.consumer_with_manual_commit(&config.group_id(), config.max_poll_interval());
+    consumer.subscribe(&vec![config.from_topic().as_str()]).unwrap();
+    let producer = queue.transactional_producer(&config.transactional_id());
+    let to_topic = config.to_topic();
+    let send_retries = config.send_retries();
+
+    let mut progress = Progress::new("judged games".to_owned());
+    let mut judgements = Vec::new();
+    let mut summaries = Vec::new();
+    let mut pending_offsets: HashMap<(String, i32), i64> = HashMap::new();
+    let mut pending_games = 0;
+
+    loop {
+        let msg = match timeout(IDLE_TIMEOUT, consumer.recv()).await {
+            Ok(msg) => Some(msg.unwrap()),
+            Err(_) => None,
+        };
+
+        if let Some(msg) = &msg {
+            let game = ChessGame::decode(msg.payload().unwrap()).unwrap();
+            // games parsed before game ids were introduced only have random key
+            let game_id = if game.game_id.is_empty() {
+                base64::encode(msg.key().unwrap())
+            } else {
+                game.game_id.clone()
+            };
+
+            let mut game_judgements = into_chess_game_move_judgement_entities(&game_id, &game);
+            if !game_judgements.is_empty() {
+                summaries.push((game_id.clone(), summarize(&game_id, &game_judgements).encode_to_vec()));
+                judgements.append(&mut game_judgements);
+            }
+
+            pending_offsets.insert((msg.topic().to_owned(), msg.partition()), msg.offset());
+            pending_games += 1;
+            progress.update();
+        }
+
+        if pending_games >= config.flush_games() || (msg.is_none() && pending_games > 0) {
+            if !judgements.is_empty() {
+                write_csv_data_file(&storage, variant, GAME_DATA_MOVE_JUDGEMENTS, &judgements).await;
+                judgements.clear();
+            }
+
+            // summaries are produced in one transaction with consumer offsets, once judgements of the games are in object
+            // storage. A crash before the commit uploads judgements of these games again, but not their summaries.
+            let mut offsets = TopicPartitionList::new();
+            for ((topic, partition), offset) in pending_offsets.drain() {
+                offsets.add_partition_offset(&topic, partition, Offset::Offset(offset + 1)).unwrap();
+            }
+
+            let mut attempt = 0;
+            while let Err(err) = send_summaries(&producer, &consumer, &to_topic, &summaries, &offsets).await {
+                if let Err(err) = producer.abort_transaction(Duration::from_secs(10)) {
+                    error!("failed to abort transaction: {}", err);
+                }
+
+                if attempt >= send_retries {
+                    return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("failed to send game judgements: {}", err)));
+                }
+                attempt += 1;
+                warn!("failed to send game judgements (attempt {}/{}): {}", attempt, send_retries, err);
+                sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            }
+            summaries.clear();
+            pending_games = 0;
+        }
+    }
+}
+
+async fn send_summaries(
+    producer: &FutureProducer,
+    consumer: &StreamConsumer<StreamingContext>,
+    to_topic: &str,
+    summaries: &[(String, Vec<u8>)],
+    offsets: &TopicPartitionList,
+) -> KafkaResult<()> {
+    producer.begin_transaction()?;
+
+    for (game_id, summary) in summaries {
+        producer.send(
+            FutureRecord::to(to_topic)
+                .payload(summary)
+                .key(game_id),
+            Duration::from_secs(10)
+        ).await.map_err(|(err, _)| err)?;
+    }
+
+    producer.send_offsets_to_transaction(offsets, &consumer.group_metadata().unwrap(), Duration::from_secs(10))?;
+    producer.commit_transaction(Duration::from_secs(10))
+}
+
+fn summarize(game_id: &str, judgements: &[ChessGameMoveJudgementEntity]) -> GameJudgements {
+    let mut white = SideJudgements::default();
+    let mut black = SideJudgements::default();
+
+    for judgement in judgements {
+        let side = if judgement.side() == "white" { &mut white } else { &mut black };
+        side.evaluated_moves += 1;
+
+        match judgement.judgement() {
+            Some("inaccuracy") => side.inaccuracies += 1,
+            Some("mistake") => side.mistakes += 1,
+            Some("blunder") => side.blunders += 1,
+            _ => {},
+        }
+    }
+
+    GameJudgements {
+        game_id: game_id.to_owned(),
+        white: Some(white),
+        black: Some(black),
+    }
+}
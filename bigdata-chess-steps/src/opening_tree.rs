@@ -0,0 +1,356 @@
+use {
+    std::{sync::Arc, path::Path, collections::HashMap, time::Duration},
+    tracing::info,
+    rdkafka::{Message, consumer::{Consumer, CommitMode}},
+    prost::Message as ProstMessage,
+    tokio::time::timeout,
+    leveldb::{
+        database::Database,
+        batch::{Batch, Writebatch},
+        iterator::{Iterable, LevelDBIterator},
+        kv::KV,
+        options::{Options, ReadOptions, WriteOptions},
+    },
+    shakmaty::{
+        fen::Fen,
+        san::{San as ShakmatySan, SanPlus},
+        Chess,
+        CastlingMode,
+        CastlingSide,
+        EnPassantMode,
+        File,
+        Position,
+        Rank,
+        Role,
+        Square,
+    },
+    bigdata_chess_core::{
+        config::OpeningTreeStepConfig,
+        queue::Queue,
+        lichess::LichessSpeed,
+        data::{ChessGame, GameResult, San, Speed, Variant},
+    },
+    crate::{
+        byte_key::ByteKey,
+        progress::Progress,
+    },
+};
+
+// pending stats are written when no new games arrive for this long, so that the last games of the topic are not held back
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Default, Clone, Debug)]
+pub struct MoveStats {
+    pub games: u64,
+    pub white_wins: u64,
+    pub draws: u64,
+    pub black_wins: u64,
+    elo_sum: u64,
+}
+
+// keys are position, rating band, speed and move separated by zero bytes, so that all moves from a position can be
+// read with a prefix scan. Offset of the last game added from each partition is stored too, so that games which are
+// received again after a crash between writing stats and committing consumer offsets are not counted twice.
+pub struct OpeningTree {
+    database: Database<ByteKey>,
+}
+
+// aggregates moves played from each position in the first `max_ply` plies of standard games into leveldb
+pub async fn opening_tree_step(config: &OpeningTreeStepConfig, queue: Arc<Queue>) -> std::io::Result<()> {
+    info!("running opening tree step");
+
+    let consumer = queue.consumer_with_manual_commit(&config.group_id(), config.max_poll_interval());
+    consumer.subscribe(&vec![config.from_topic().as_str()]).unwrap();
+
+    let tree = OpeningTree::open(&config.database_path());
+    let mut progress = Progress::new("games added to opening tree".to_owned());
+
+    let mut pending: HashMap<Vec<u8>, MoveStats> = HashMap::new();
+    let mut pending_offsets: HashMap<(String, i32), i64> = HashMap::new();
+    let mut pending_games = 0;
+    let mut applied_offsets: HashMap<(String, i32), Option<i64>> = HashMap::new();
+
+    loop {
+        let msg = match timeout(IDLE_TIMEOUT, consumer.recv()).await {
+            Ok(msg) => Some(msg.unwrap()),
+            Err(_) => None,
+        };
+
+        if let Some(msg) = &msg {
+            let partition = (msg.topic().to_owned(), msg.partition());
+            let applied_offset = *applied_offsets.entry(partition.clone())
+                .or_insert_with(|| tree.applied_offset(&partition.0, partition.1));
+
+            // games received again are still counted, so that consumer offsets get committed past them
+            if applied_offset.map(|v| msg.offset() > v).unwrap_or(true) {
+                pending_offsets.insert(partition, msg.offset());
+
+                let game = ChessGame::decode(msg.payload().unwrap()).unwrap();
+
+                // the tree only covers games from the standard starting position
+                if game.variant() == Variant::Standard && game.starting_fen.is_empty() {
+                    add_game(&mut pending, &game, config.max_ply(), config.rating_band_width());
+                }
+            }
+
+            pending_games += 1;
+            progress.update();
+        }
+
+        if pending_games >= config.flush_games() || (msg.is_none() && pending_games > 0) {
+            for (partition, offset) in &pending_offsets {
+                applied_offsets.insert(partition.clone(), Some(*offset));
+            }
+            tree.add(std::mem::take(&mut pending), std::mem::take(&mut pending_offsets));
+            consumer.commit_consumer_state(CommitMode::Sync).unwrap();
+            pending_games = 0;
+        }
+    }
+}
+
+fn add_game(pending: &mut HashMap<Vec<u8>, MoveStats>, game: &ChessGame, max_ply: u32, rating_band_width: u32) {
+    let white_elo = game.white_player.as_ref().map(|v| v.elo).unwrap_or(0);
+    let black_elo = game.black_player.as_ref().map(|v| v.elo).unwrap_or(0);
+    let average_elo = (white_elo + black_elo) / 2;
+    let rating_band = average_elo / rating_band_width * rating_band_width;
+    // games parsed before speed was introduced only have timecontrol
+    let speed = game.speed.and_then(Speed::from_i32)
+        .map(LichessSpeed::from)
+        .unwrap_or_else(|| LichessSpeed::from_game_timecontrol(game.timecontrol.as_ref()));
+
+    let mut game_stats = MoveStats {
+        games: 1,
+        elo_sum: average_elo as u64,
+        ..MoveStats::default()
+    };
+    match game.result() {
+        GameResult::WhiteWins => game_stats.white_wins = 1,
+        GameResult::BlackWins => game_stats.black_wins = 1,
+        GameResult::Draw => game_stats.draws = 1,
+        GameResult::Star => {},
+    }
+
+    let mut position = Chess::default();
+    let mut ply = 0;
+
+    for san in game.game_entries.iter().filter_map(|v| v.san.as_ref()) {
+        if ply >= max_ply {
+            break;
+        }
+
+        let m = match shakmaty_san(san).and_then(|v| v.to_move(&position).ok()) {
+            Some(v) => v,
+            None => break,
+        };
+
+        let key = opening_tree_key(&position_key(&position), Some(rating_band), Some(speed), Some(&ShakmatySan::from_move(&position, &m).to_string()));
+        pending.entry(key).or_default().add(&game_stats);
+
+        position.play_unchecked(&m);
+        ply += 1;
+    }
+}
+
+impl OpeningTree {
+    pub fn open(path: &str) -> Self {
+        let mut options = Options::new();
+        options.create_if_missing = true;
+
+        Self {
+            database: Database::open(Path::new(path), options).unwrap(),
+        }
+    }
+
+    // stats and offsets of the games they come from are written in one batch
+    pub fn add(&self, stats: HashMap<Vec<u8>, MoveStats>, offsets: HashMap<(String, i32), i64>) {
+        let mut batch = Writebatch::new();
+
+        for (key, stats) in stats {
+            let mut total = self.database.get(ReadOptions::new(), ByteKey(key.clone()))
+                .unwrap()
+                .map(|v| MoveStats::from_bytes(&v))
+                .unwrap_or_default();
+            total.add(&stats);
+
+            batch.put(ByteKey(key), &total.to_bytes());
+        }
+
+        for ((topic, partition), offset) in offsets {
+            batch.put(ByteKey(applied_offset_key(&topic, partition)), &offset.to_be_bytes());
+        }
+
+        self.database.write(WriteOptions::new(), &batch).unwrap();
+    }
+
+    fn applied_offset(&self, topic: &str, partition: i32) -> Option<i64> {
+        self.database.get(ReadOptions::new(), ByteKey(applied_offset_key(topic, partition)))
+            .unwrap()
+            .map(|v| i64::from_be_bytes(v.as_slice().try_into().unwrap()))
+    }
+
+    // stats of moves played from the position, summed over rating bands and speeds which are not given. Most popular
+    // moves come first.
+    pub fn moves(&self, position_key: &str, rating_band: Option<u32>, speed: Option<LichessSpeed>) -> Vec<(String, MoveStats)> {
+        let prefix = opening_tree_key(position_key, None, None, None);
+        let start = ByteKey(prefix.clone());
+
+        let mut moves: HashMap<String, MoveStats> = HashMap::new();
+        for (key, value) in self.database.iter(ReadOptions::new()).from(&start) {
+            if !key.0.starts_with(&prefix) {
+                break;
+            }
+
+            let parts: Vec<&[u8]> = key.0[prefix.len()..].split(|v| *v == 0).collect();
+            let (key_rating_band, key_speed, san) = match parts.as_slice() {
+                [rating_band, speed, san] => (
+                    String::from_utf8_lossy(rating_band).to_string(),
+                    String::from_utf8_lossy(speed).to_string(),
+                    String::from_utf8_lossy(san).to_string(),
+                ),
+                _ => continue,
+            };
+
+            if rating_band.map(|v| v.to_string() != key_rating_band).unwrap_or(false) {
+                continue;
+            }
+            if speed.map(|v| v.name() != key_speed).unwrap_or(false) {
+                continue;
+            }
+
+            moves.entry(san).or_default().add(&MoveStats::from_bytes(&value));
+        }
+
+        let mut moves: Vec<_> = moves.into_iter().collect();
+        moves.sort_by(|a, b| b.1.games.cmp(&a.1.games));
+        moves
+    }
+}
+
+impl MoveStats {
+    fn add(&mut self, other: &MoveStats) {
+        self.games += other.games;
+        self.white_wins += other.white_wins;
+        self.draws += other.draws;
+        self.black_wins += other.black_wins;
+        self.elo_sum += other.elo_sum;
+    }
+
+    pub fn average_elo(&self) -> u64 {
+        if self.games == 0 {
+            0
+        } else {
+            self.elo_sum / self.games
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        [self.games, self.white_wins, self.draws, self.black_wins, self.elo_sum]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect()
+    }
+
+    fn from_bytes(data: &[u8]) -> Self {
+        let values: Vec<u64> = data.chunks_exact(8)
+            .map(|v| u64::from_be_bytes(v.try_into().unwrap()))
+            .collect();
+
+        Self {
+            games: values[0],
+            white_wins: values[1],
+            draws: values[2],
+            black_wins: values[3],
+            elo_sum: values[4],
+        }
+    }
+}
+
+// position to look moves up for: either fen, or moves in san played from the starting position
+pub fn position_key_for_query(fen: Option<&str>, moves: Option<&str>) -> Result<String, String> {
+    let mut position = match fen {
+        Some(fen) => {
+            let fen: Fen = fen.parse().map_err(|err| format!("failed to parse fen: {}", err))?;
+            fen.into_position(CastlingMode::Standard).map_err(|err| format!("invalid position: {}", err))?
+        },
+        None => Chess::default(),
+    };
+
+    for san in moves.unwrap_or("").split_whitespace() {
+        let san: SanPlus = san.parse().map_err(|err| format!("failed to parse move {}: {}", san, err))?;
+        let m = san.san.to_move(&position).map_err(|err| format!("illegal move {}: {}", san, err))?;
+        position.play_unchecked(&m);
+    }
+
+    Ok(position_key(&position))
+}
+
+// fen without move counters, so that transpositions end up in the same position
+fn position_key(position: &Chess) -> String {
+    Fen::from_position(position.clone(), EnPassantMode::Legal)
+        .to_string()
+        .split(' ')
+        .take(4)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn opening_tree_key(position_key: &str, rating_band: Option<u32>, speed: Option<LichessSpeed>, san: Option<&str>) -> Vec<u8> {
+    let mut key = position_key.as_bytes().to_vec();
+    key.push(0);
+
+    if let Some(rating_band) = rating_band {
+        key.extend_from_slice(rating_band.to_string().as_bytes());
+        key.push(0);
+    }
+    if let Some(speed) = speed {
+        key.extend_from_slice(speed.name().as_bytes());
+        key.push(0);
+    }
+    if let Some(san) = san {
+        key.extend_from_slice(san.as_bytes());
+    }
+
+    key
+}
+
+// starts with zero byte, so that it never matches a position prefix
+fn applied_offset_key(topic: &str, partition: i32) -> Vec<u8> {
+    format!("\0offset\0{}\0{}", topic, partition).into_bytes()
+}
+
+fn shakmaty_san(san: &San) -> Option<ShakmatySan> {
+    if let Some(normal) = &san.normal {
+        let to = normal.to.as_ref()?;
+        Some(ShakmatySan::Normal {
+            role: *Role::ALL.get(normal.role as usize)?,
+            file: match normal.file {
+                Some(v) => Some(*File::ALL.get(v as usize)?),
+                None => None,
+            },
+            rank: match normal.rank {
+                Some(v) => Some(*Rank::ALL.get(v as usize)?),
+                None => None,
+            },
+            capture: normal.capture,
+            to: square(to.file, to.rank)?,
+            promotion: match normal.promotion {
+                Some(v) => Some(*Role::ALL.get(v as usize)?),
+                None => None,
+            },
+        })
+    } else if let Some(castle) = &san.castle {
+        Some(ShakmatySan::Castle(if castle.side == 0 { CastlingSide::KingSide } else { CastlingSide::QueenSide }))
+    } else if let Some(put) = &san.put {
+        let to = put.to.as_ref()?;
+        Some(ShakmatySan::Put {
+            role: *Role::ALL.get(put.role as usize)?,
+            to: square(to.file, to.rank)?,
+        })
+    } else {
+        None
+    }
+}
+
+fn square(file: i32, rank: i32) -> Option<Square> {
+    Some(Square::from_coords(*File::ALL.get(file as usize)?, *Rank::ALL.get(rank as usize)?))
+}
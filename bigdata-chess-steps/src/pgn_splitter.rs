@@ -0,0 +1,394 @@
+use bigdata_chess_core::data::RawChessGame;
+
+const RESULT_TOKENS: [&[u8]; 4] = [b"1-0", b"0-1", b"1/2-1/2", b"*"];
+
+pub enum PgnFragment {
+    Game(RawChessGame),
+    // anything which does not look like header block followed by movetext ending with result
+    Malformed(Vec<u8>),
+}
+
+#[derive(PartialEq, Eq)]
+enum State {
+    BetweenGames,
+    Headers,
+    Movetext,
+}
+
+// Splits stream of pgn data into games. Works on bytes, so that multi-byte characters split between reads are not
+// corrupted. A game is a block of header lines followed by movetext, which ends with a result token.
+// Each fragment comes with offset in the stream, starting from which splitting can be resumed to get the following
+// fragments.
+pub struct PgnSplitter {
+    offset: u64,
+    line: Vec<u8>,
+    state: State,
+    headers: Vec<u8>,
+    movetext: Vec<u8>,
+    malformed: Vec<u8>,
+    comment_depth: usize,
+}
+
+impl PgnSplitter {
+    pub fn new() -> Self {
+        Self::starting_at(0)
+    }
+
+    // for streams which are read not from the beginning, offset is the one of a previously returned fragment
+    pub fn starting_at(offset: u64) -> Self {
+        Self {
+            offset,
+            line: Vec::new(),
+            state: State::BetweenGames,
+            headers: Vec::new(),
+            movetext: Vec::new(),
+            malformed: Vec::new(),
+            comment_depth: 0,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Vec<(PgnFragment, u64)> {
+        let mut fragments = Vec::new();
+
+        for byte in data {
+            if *byte == b'\n' {
+                let line = std::mem::take(&mut self.line);
+                let line_start = self.offset;
+                self.offset += line.len() as u64 + 1;
+                self.process_line(&line, line_start, &mut fragments);
+            } else {
+                self.line.push(*byte);
+            }
+        }
+
+        fragments
+    }
+
+    // to be called at the end of the stream. Incomplete game at the end is reported as malformed.
+    pub fn finish(&mut self) -> Vec<(PgnFragment, u64)> {
+        let mut fragments = Vec::new();
+
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            let line_start = self.offset;
+            self.offset += line.len() as u64;
+            self.process_line(&line, line_start, &mut fragments);
+        }
+
+        self.flush_incomplete_game();
+        self.flush_malformed(self.offset, &mut fragments);
+
+        fragments
+    }
+
+    // fragments which end before a header line get its start offset, so that the header is read again on resume
+    fn process_line(&mut self, line: &[u8], line_start: u64, fragments: &mut Vec<(PgnFragment, u64)>) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let is_blank = line.iter().all(|v| v.is_ascii_whitespace());
+        let is_header = line.first() == Some(&b'[') && self.comment_depth == 0;
+
+        match self.state {
+            State::BetweenGames => {
+                if is_header {
+                    self.flush_malformed(line_start, fragments);
+                    self.headers.extend_from_slice(line);
+                    self.state = State::Headers;
+                } else if !is_blank {
+                    append_line(&mut self.malformed, line);
+                }
+            },
+            State::Headers => {
+                if is_header {
+                    append_line(&mut self.headers, line);
+                } else if is_blank {
+                    self.state = State::Movetext;
+                } else {
+                    // blank line between headers and movetext is missing, which is fine
+                    self.state = State::Movetext;
+                    self.process_movetext_line(line, fragments);
+                }
+            },
+            State::Movetext => {
+                if is_header {
+                    // previous game was truncated before result
+                    self.flush_incomplete_game();
+                    self.flush_malformed(line_start, fragments);
+                    self.headers.extend_from_slice(line);
+                    self.state = State::Headers;
+                } else if is_blank {
+                    if !self.movetext.is_empty() && self.comment_depth == 0 {
+                        // movetext ended without result
+                        self.flush_incomplete_game();
+                    }
+                } else {
+                    self.process_movetext_line(line, fragments);
+                }
+            },
+        }
+    }
+
+    fn process_movetext_line(&mut self, line: &[u8], fragments: &mut Vec<(PgnFragment, u64)>) {
+        append_line(&mut self.movetext, line);
+
+        for byte in line {
+            match byte {
+                b'{' => self.comment_depth += 1,
+                b'}' => self.comment_depth = self.comment_depth.saturating_sub(1),
+                _ => {},
+            }
+        }
+
+        let ends_with_result = self.comment_depth == 0 && line
+            .split(|v| v.is_ascii_whitespace())
+            .filter(|v| !v.is_empty())
+            .last()
+            .map(|v| RESULT_TOKENS.contains(&v))
+            .unwrap_or(false);
+
+        if ends_with_result {
+            let headers = std::mem::take(&mut self.headers);
+            let movetext = std::mem::take(&mut self.movetext);
+            self.state = State::BetweenGames;
+
+            match (String::from_utf8(headers), String::from_utf8(movetext)) {
+                (Ok(metadata), Ok(moves)) => fragments.push((PgnFragment::Game(RawChessGame {
+                    metadata,
+                    moves,
+                }), self.offset)),
+                (headers, movetext) => {
+                    let mut malformed = headers.map(|v| v.into_bytes()).unwrap_or_else(|err| err.into_bytes());
+                    malformed.extend_from_slice(b"\n\n");
+                    malformed.append(&mut movetext.map(|v| v.into_bytes()).unwrap_or_else(|err| err.into_bytes()));
+                    fragments.push((PgnFragment::Malformed(malformed), self.offset));
+                },
+            }
+        }
+    }
+
+    fn flush_incomplete_game(&mut self) {
+        if !self.headers.is_empty() || !self.movetext.is_empty() {
+            append_line(&mut self.malformed, &std::mem::take(&mut self.headers));
+            append_line(&mut self.malformed, &std::mem::take(&mut self.movetext));
+        }
+
+        self.state = State::BetweenGames;
+        self.comment_depth = 0;
+    }
+
+    fn flush_malformed(&mut self, offset: u64, fragments: &mut Vec<(PgnFragment, u64)>) {
+        if !self.malformed.is_empty() {
+            fragments.push((PgnFragment::Malformed(std::mem::take(&mut self.malformed)), offset));
+        }
+    }
+}
+
+fn append_line(target: &mut Vec<u8>, line: &[u8]) {
+    if !target.is_empty() {
+        target.push(b'\n');
+    }
+    target.extend_from_slice(line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // first two games of lichess_db_standard_rated_2013-01
+    const FIRST_GAME: &str = "[Event \"Rated Classical game\"]
+[Site \"https://lichess.org/j1dkb5dw\"]
+[White \"BFG9k\"]
+[Black \"mamalak\"]
+[Result \"1-0\"]
+[UTCDate \"2012.12.31\"]
+[UTCTime \"23:01:03\"]
+[WhiteElo \"1639\"]
+[BlackElo \"1403\"]
+[WhiteRatingDiff \"+5\"]
+[BlackRatingDiff \"-8\"]
+[ECO \"C00\"]
+[Opening \"French Defense: Normal Variation\"]
+[TimeControl \"600+8\"]
+[Termination \"Normal\"]
+
+1. e4 e6 2. d4 b6 3. a3 Bb7 4. Nc3 Nh6 5. Bxh6 gxh6 6. Be2 Qg5 7. Bg4 h5 8. Nf3 Qg6 9. Nh4 Qg5 10. Bxh5 Qxh4 11. Qf3 Kd8 12. Qxf7 Nc6 13. Qe8# 1-0
+
+";
+
+    const SECOND_GAME: &str = "[Event \"Rated Classical game\"]
+[Site \"https://lichess.org/a9tcp02g\"]
+[White \"Desmond_Wilson\"]
+[Black \"savinka59\"]
+[Result \"1-0\"]
+[UTCDate \"2012.12.31\"]
+[UTCTime \"23:04:12\"]
+[WhiteElo \"1654\"]
+[BlackElo \"1919\"]
+[WhiteRatingDiff \"+19\"]
+[BlackRatingDiff \"-22\"]
+[ECO \"D04\"]
+[Opening \"Queen's Pawn Game: Colle System, Anti-Colle\"]
+[TimeControl \"480+2\"]
+[Termination \"Normal\"]
+
+1. d4 d5 2. Nf3 Nf6 3. e3 Bf5 4. Nh4 Bg6 5. Nxg6 hxg6 6. Nd2 e6 7. Bd3 Bd6 8. e4 dxe4 9. Nxe4 Rxh2 10. Ke2 Rxh1 11. Qxh1 Nc6 12. Bg5 Ke7 13. Qh7 Nxd4+ 14. Kd2 Qe8 15. Qxg7 Qh8 16. Bxf6+ Kd7 17. Qxh8 Rxh8 18. Bxh8 1-0
+
+";
+
+    // example game of the lichess database documentation (https://database.lichess.org), in the format of newer dumps
+    const EXAMPLE_GAME: &str = "[Event \"Rated Bullet tournament https://lichess.org/tournament/yc1WW2Ox\"]
+[Site \"https://lichess.org/PpwPOZMq\"]
+[Date \"2017.04.01\"]
+[Round \"-\"]
+[White \"Abbot\"]
+[Black \"Costello\"]
+[Result \"0-1\"]
+[UTCDate \"2017.04.01\"]
+[UTCTime \"11:32:01\"]
+[WhiteElo \"2100\"]
+[BlackElo \"2000\"]
+[WhiteRatingDiff \"-4\"]
+[BlackRatingDiff \"+1\"]
+[WhiteTitle \"FM\"]
+[ECO \"B30\"]
+[Opening \"Sicilian Defense: Old Sicilian\"]
+[TimeControl \"300+0\"]
+[Termination \"Time forfeit\"]
+
+1. e4 { [%eval 0.17] [%clk 0:00:30] } 1... c5 { [%eval 0.19] [%clk 0:00:30] } 2. Nf3 { [%eval 0.25] [%clk 0:00:29] } 2... Nc6 { [%eval 0.33] [%clk 0:00:30] } 3. Bc4 { [%eval -0.13] [%clk 0:00:28] } 3... e6 { [%eval -0.04] [%clk 0:00:30] } 4. c3 { [%eval -0.4] [%clk 0:00:27] } 4... b5? { [%eval 1.18] [%clk 0:00:30] } 5. Bb3?! { [%eval 0.21] [%clk 0:00:26] } 5... c4 { [%eval 0.32] [%clk 0:00:29] } 6. Bc2 { [%eval 0.2] [%clk 0:00:25] } 6... a5 { [%eval 0.6] [%clk 0:00:29] } 7. d4 { [%eval 0.29] [%clk 0:00:23] } 7... cxd3 { [%eval 0.6] [%clk 0:00:27] } 8. Qxd3 { [%eval 0.12] [%clk 0:00:22] } 8... Nf6 { [%eval 0.52] [%clk 0:00:26] } 9. e5 { [%eval 0.39] [%clk 0:00:21] } 9... Nd5 { [%eval 0.45] [%clk 0:00:25] } 10. Bg5?! { [%eval -0.44] [%clk 0:00:18] } 10... Qc7 { [%eval -0.12] [%clk 0:00:23] } 11. Nbd2?? { [%eval -3.15] [%clk 0:00:14] } 11... h6 { [%eval -2.99] [%clk 0:00:23] } 12. Bh4 { [%eval -3.0] [%clk 0:00:11] } 12... Ba6? { [%eval -0.12] [%clk 0:00:23] } 13. b3?? { [%eval -4.14] [%clk 0:00:02] } 13... Nf4? { [%eval -2.73] [%clk 0:00:21] } 0-1
+
+";
+
+    fn split(chunks: &[&[u8]]) -> Vec<(PgnFragment, u64)> {
+        let mut splitter = PgnSplitter::new();
+        let mut fragments = Vec::new();
+        for chunk in chunks {
+            fragments.append(&mut splitter.push(chunk));
+        }
+        fragments.append(&mut splitter.finish());
+        fragments
+    }
+
+    fn game(fragment: &(PgnFragment, u64)) -> &RawChessGame {
+        match &fragment.0 {
+            PgnFragment::Game(game) => game,
+            PgnFragment::Malformed(data) => panic!("expected game, got malformed fragment: {}", String::from_utf8_lossy(data)),
+        }
+    }
+
+    fn malformed(fragment: &(PgnFragment, u64)) -> &[u8] {
+        match &fragment.0 {
+            PgnFragment::Malformed(data) => data,
+            PgnFragment::Game(game) => panic!("expected malformed fragment, got game: {}", game.metadata),
+        }
+    }
+
+    #[test]
+    fn splits_games() {
+        let data = format!("{}{}", FIRST_GAME, SECOND_GAME);
+        let fragments = split(&[data.as_bytes()]);
+
+        assert_eq!(fragments.len(), 2);
+        assert!(game(&fragments[0]).metadata.starts_with("[Event \"Rated Classical game\"]\n[Site \"https://lichess.org/j1dkb5dw\"]"));
+        assert!(game(&fragments[0]).metadata.ends_with("[Termination \"Normal\"]"));
+        assert!(game(&fragments[0]).moves.starts_with("1. e4 e6"));
+        assert!(game(&fragments[0]).moves.ends_with("13. Qe8# 1-0"));
+        assert!(game(&fragments[1]).metadata.contains("https://lichess.org/a9tcp02g"));
+        assert!(game(&fragments[1]).moves.ends_with("18. Bxh8 1-0"));
+    }
+
+    #[test]
+    fn keeps_multi_byte_characters_split_between_pushes() {
+        // lichess usernames are ascii, but pgn files from elsewhere have names like this one
+        let data = SECOND_GAME.replace("[White \"Desmond_Wilson\"]", "[White \"Şükrü_Öztürk\"]");
+        let split_at = data.find('ü').unwrap() + 1;
+        assert!(!data.is_char_boundary(split_at));
+
+        let fragments = split(&[&data.as_bytes()[..split_at], &data.as_bytes()[split_at..]]);
+
+        assert_eq!(fragments.len(), 1);
+        assert!(game(&fragments[0]).metadata.contains("[White \"Şükrü_Öztürk\"]"));
+        assert!(game(&fragments[0]).moves.ends_with("18. Bxh8 1-0"));
+    }
+
+    #[test]
+    fn gives_same_games_when_pushed_byte_by_byte() {
+        let data = format!("{}{}{}", FIRST_GAME, EXAMPLE_GAME, SECOND_GAME);
+        let whole = split(&[data.as_bytes()]);
+        let chunks: Vec<&[u8]> = data.as_bytes().chunks(1).collect();
+        let by_byte = split(&chunks);
+
+        assert_eq!(whole.len(), 3);
+        assert_eq!(by_byte.len(), 3);
+        for (whole, by_byte) in whole.iter().zip(by_byte.iter()) {
+            assert_eq!(game(whole), game(by_byte));
+            assert_eq!(whole.1, by_byte.1);
+        }
+    }
+
+    #[test]
+    fn accepts_missing_blank_line_between_headers_and_movetext() {
+        let data = FIRST_GAME.replacen("[Termination \"Normal\"]\n\n", "[Termination \"Normal\"]\n", 1);
+        let fragments = split(&[data.as_bytes()]);
+
+        assert_eq!(fragments.len(), 1);
+        assert!(game(&fragments[0]).metadata.ends_with("[Termination \"Normal\"]"));
+        assert!(game(&fragments[0]).moves.starts_with("1. e4 e6"));
+        assert!(game(&fragments[0]).moves.ends_with("1-0"));
+    }
+
+    #[test]
+    fn ignores_results_and_header_lines_in_comments() {
+        let movetext = "1. e4 { [%eval 0.17] [%clk 0:03:00] } 1... e5 { [%eval 0.22] [%clk 0:03:00] } 2. Qh5 { looks like 1-0
+[%clk 0:02:58] soon } 2... Nc6 { [%eval 0.0] [%clk 0:02:57] } 0-1";
+        let data = FIRST_GAME.replace(
+            "1. e4 e6 2. d4 b6 3. a3 Bb7 4. Nc3 Nh6 5. Bxh6 gxh6 6. Be2 Qg5 7. Bg4 h5 8. Nf3 Qg6 9. Nh4 Qg5 10. Bxh5 Qxh4 11. Qf3 Kd8 12. Qxf7 Nc6 13. Qe8# 1-0",
+            movetext,
+        );
+        let data = format!("{}{}", data, SECOND_GAME);
+        let fragments = split(&[data.as_bytes()]);
+
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(game(&fragments[0]).moves, movetext);
+        assert!(game(&fragments[1]).metadata.contains("https://lichess.org/a9tcp02g"));
+    }
+
+    #[test]
+    fn reports_truncated_game_followed_by_header_as_malformed() {
+        let truncated = &FIRST_GAME[..FIRST_GAME.find("5. Bxh6").unwrap()];
+        let data = format!("{}\n{}", truncated.trim_end(), SECOND_GAME);
+        let fragments = split(&[data.as_bytes()]);
+
+        assert_eq!(fragments.len(), 2);
+        let malformed = String::from_utf8(malformed(&fragments[0]).to_vec()).unwrap();
+        assert!(malformed.contains("https://lichess.org/j1dkb5dw"));
+        assert!(malformed.ends_with("4. Nc3 Nh6"));
+        // splitting resumes from the header of the next game
+        assert_eq!(fragments[0].1, truncated.trim_end().len() as u64 + 1);
+        assert!(game(&fragments[1]).metadata.contains("https://lichess.org/a9tcp02g"));
+    }
+
+    #[test]
+    fn resumes_from_returned_offsets() {
+        let data = format!("{}{}{}", FIRST_GAME, EXAMPLE_GAME, SECOND_GAME);
+        let fragments = split(&[data.as_bytes()]);
+        assert_eq!(fragments.len(), 3);
+
+        // offset of a game points right after the line with its result
+        let first_offset = fragments[0].1 as usize;
+        assert_eq!(&data[..first_offset], FIRST_GAME.trim_end_matches('\n').to_owned() + "\n");
+
+        for (index, fragment) in fragments.iter().enumerate() {
+            let offset = fragment.1;
+            let mut splitter = PgnSplitter::starting_at(offset);
+            let mut resumed = splitter.push(&data.as_bytes()[offset as usize..]);
+            resumed.append(&mut splitter.finish());
+
+            let expected = &fragments[index + 1..];
+            assert_eq!(resumed.len(), expected.len());
+            for (resumed, expected) in resumed.iter().zip(expected.iter()) {
+                assert_eq!(game(resumed), game(expected));
+                assert_eq!(resumed.1, expected.1);
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use {
+    std::{sync::Arc, path::Path, collections::BTreeMap, time::Duration},
+    tracing::info,
+    rdkafka::{Message, consumer::{Consumer, CommitMode}},
+    prost::Message as ProstMessage,
+    tokio::time::timeout,
+    leveldb::{
+        database::Database,
+        batch::{Batch, Writebatch},
+        iterator::{Iterable, LevelDBIterator},
+        kv::KV,
+        options::{Options, ReadOptions, WriteOptions},
+    },
+    bigdata_chess_core::{
+        config::PositionIndexStepConfig,
+        queue::Queue,
+        storage::{Storage, GAME_DATA_POSITIONS},
+        entity::{ChessGamePositionEntity, into_chess_game_position_entities},
+        data::ChessGame,
+    },
+    crate::{
+        byte_key::ByteKey,
+        progress::Progress,
+        data_file::write_csv_data_file,
+    },
+};
+
+// positions are written when no new games arrive for this long, so that the last games of the topic are not held back
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// keys are position hash followed by game id, values are plies at which the game reached the position. Games
+// reaching a position are read with a prefix scan, and reprocessing a game overwrites its keys.
+pub struct PositionIndex {
+    database: Database<ByteKey>,
+}
+
+// writes positions of every game to object storage (to be imported into hive) and to the local index
+pub async fn position_index_step(config: &PositionIndexStepConfig, queue: Arc<Queue>, storage: Arc<Storage>) -> std::io::Result<()> {
+    let variant = config.variant();
+    info!("running position index step for {} games", variant.name());
+
+    let consumer = queue.consumer_with_manual_commit(&config.group_id(), config.max_poll_interval());
+    consumer.subscribe(&vec![config.from_topic().as_str()]).unwrap();
+
+    let index = PositionIndex::open(&config.database_path());
+    let mut progress = Progress::new("indexed games".to_owned());
+
+    let mut positions = Vec::new();
+    let mut pending_games = 0;
+
+    loop {
+        let msg = match timeout(IDLE_TIMEOUT, consumer.recv()).await {
+            Ok(msg) => Some(msg.unwrap()),
+            Err(_) => None,
+        };
+
+        if let Some(msg) = &msg {
+            let game = ChessGame::decode(msg.payload().unwrap()).unwrap();
+            // games parsed before game ids were introduced only have random key
+            let game_id = if game.game_id.is_empty() {
+                base64::encode(msg.key().unwrap())
+            } else {
+                game.game_id.clone()
+            };
+
+            positions.append(&mut into_chess_game_position_entities(&game_id, &game));
+            pending_games += 1;
+            progress.update();
+        }
+
+        if pending_games >= config.flush_games() || (msg.is_none() && pending_games > 0) {
+            index.add(&positions);
+
+            write_csv_data_file(&storage, variant, GAME_DATA_POSITIONS, &positions).await;
+            positions.clear();
+
+            consumer.commit_consumer_state(CommitMode::Sync).unwrap();
+            pending_games = 0;
+        }
+    }
+}
+
+impl PositionIndex {
+    pub fn open(path: &str) -> Self {
+        let mut options = Options::new();
+        options.create_if_missing = true;
+
+        Self {
+            database: Database::open(Path::new(path), options).unwrap(),
+        }
+    }
+
+    pub fn add(&self, positions: &[ChessGamePositionEntity]) {
+        // the same position can be reached multiple times in a game
+        let mut plies: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        for position in positions {
+            plies.entry(position_index_key(position.position_hash(), Some(position.game_id())))
+                .or_default()
+                .extend_from_slice(&position.ply().to_be_bytes());
+        }
+
+        let mut batch = Writebatch::new();
+        for (key, value) in plies {
+            batch.put(ByteKey(key), &value);
+        }
+        self.database.write(WriteOptions::new(), &batch).unwrap();
+    }
+
+    // ids of games which reached the position, with plies at which they did
+    pub fn games(&self, position_hash: i64, limit: usize) -> Vec<(String, Vec<u32>)> {
+        let prefix = position_index_key(position_hash, None);
+        let start = ByteKey(prefix.clone());
+
+        self.database.iter(ReadOptions::new())
+            .from(&start)
+            .take_while(|(key, _)| key.0.starts_with(&prefix))
+            .take(limit)
+            .map(|(key, value)| (
+                String::from_utf8_lossy(&key.0[prefix.len()..]).to_string(),
+                value.chunks_exact(4).map(|v| u32::from_be_bytes(v.try_into().unwrap())).collect(),
+            ))
+            .collect()
+    }
+}
+
+fn position_index_key(position_hash: i64, game_id: Option<&str>) -> Vec<u8> {
+    let mut key = position_hash.to_be_bytes().to_vec();
+    if let Some(game_id) = game_id {
+        key.extend_from_slice(game_id.as_bytes());
+    }
+    key
+}
@@ -17,7 +17,6 @@ use {
     crate::progress::Progress,
 };
 
-#[allow(dead_code)] // used from other crate
 pub async fn postgres_import_step(queue: Arc<Queue>, database: Arc<Database>) {
     info!("running postgres import step");
 
@@ -42,7 +41,12 @@ async fn run_consumer(queue: Arc<Queue>, database: Database, progress: Arc<Mutex
         let payload = msg.payload().unwrap();
 
         let game = ChessGame::decode(payload).unwrap();
-        let game_id = base64::encode(msg.key().unwrap());
+        // games parsed before game ids were introduced only have random key
+        let game_id = if game.game_id.is_empty() {
+            base64::encode(msg.key().unwrap())
+        } else {
+            game.game_id.clone()
+        };
 
         let mut entry_index = 0;
 
@@ -51,10 +55,8 @@ async fn run_consumer(queue: Arc<Queue>, database: Database, progress: Arc<Mutex
         for entry in &game.game_entries {
             entry_index += 1;
             if let Some(san) = &entry.san {
-                if let Some(normal) = &san.normal {
-                    let game_move_entity = into_chess_game_move_entity(&game_id, entry_index, normal, san.is_check.unwrap_or(false), san.is_checkmate.unwrap_or(false));
-                    futures.push(database.save_game_move(game_move_entity).boxed());
-                }
+                let game_move_entity = into_chess_game_move_entity(&game_id, entry_index, san, entry.board_move.as_ref());
+                futures.push(database.save_game_move(game_move_entity).boxed());
             }
         }
         
@@ -0,0 +1,117 @@
+use {
+    std::{sync::Arc, time::Duration, collections::{HashMap, BTreeMap}},
+    tracing::{info, warn},
+    rdkafka::{Message, producer::{FutureProducer, FutureRecord}},
+    prost::Message as ProstMessage,
+    tokio::time::timeout,
+    bigdata_chess_core::{
+        queue::{Queue, topic_for_variant},
+        data::GameParserError,
+        config::ReplayParserErrorsStepConfig,
+    },
+    crate::{
+        game_parser::parse_game,
+        progress::Progress,
+    },
+};
+
+#[derive(Default)]
+struct ErrorKindSummary {
+    resolved: u64,
+    unresolved: u64,
+}
+
+// re-runs game parser on games from parser errors topic, for example after parser was fixed. Finishes when there
+// are no more errors to replay.
+pub async fn replay_parser_errors_step(config: &ReplayParserErrorsStepConfig, queue: Arc<Queue>) -> std::io::Result<()> {
+    info!("running replay parser errors step");
+
+    let consumer = queue.consumer_for_topic(&config.group_id(), &config.from_topic());
+    let producer = queue.producer();
+    let to_topic = config.to_topic();
+    let failed_topic = config.failed_topic();
+
+    let mut progress = Progress::new("replayed parser errors".to_owned());
+    let mut summary: BTreeMap<String, ErrorKindSummary> = BTreeMap::new();
+
+    // there is an error message for each error in a game, but the game needs to be replayed only once
+    let mut replayed_games: HashMap<(String, i32, i64), bool> = HashMap::new();
+
+    loop {
+        let msg = match timeout(config.idle_timeout(), consumer.recv()).await {
+            Ok(msg) => msg.unwrap(),
+            Err(_) => {
+                info!("no parser errors to replay for {} seconds", config.idle_timeout().as_secs());
+                break;
+            },
+        };
+
+        let error = match GameParserError::decode(msg.payload().unwrap_or(&[])) {
+            Ok(v) => v,
+            Err(err) => {
+                // errors published before they had structure are plain strings without pgn
+                warn!("skipping parser error which cannot be decoded: {}", err);
+                continue;
+            },
+        };
+
+        let source = (error.source_topic.clone(), error.source_partition, error.source_offset);
+        let resolved = match replayed_games.get(&source) {
+            Some(resolved) => *resolved,
+            None => {
+                let resolved = replay_game(&producer, &error, &to_topic, &failed_topic).await;
+                replayed_games.insert(source, resolved);
+                resolved
+            },
+        };
+
+        let kind_summary = summary.entry(format!("{:?}", error.kind())).or_default();
+        if resolved {
+            kind_summary.resolved += 1;
+        } else {
+            kind_summary.unresolved += 1;
+        }
+
+        if progress.update() {
+            log_summary(&summary);
+        }
+    }
+
+    info!("replayed {} games", replayed_games.len());
+    log_summary(&summary);
+
+    Ok(())
+}
+
+async fn replay_game(producer: &FutureProducer, error: &GameParserError, to_topic: &str, failed_topic: &str) -> bool {
+    match parse_game(&error.raw_pgn) {
+        Ok(game) => {
+            producer.send(
+                FutureRecord::to(&topic_for_variant(to_topic, game.variant().into()))
+                    .payload(&game.encode_to_vec())
+                    .key(&game.game_id),
+                Duration::from_secs(0)
+            ).await.unwrap();
+            true
+        },
+        Err(errors) => {
+            let key = format!("{}:{}:{}", error.source_topic, error.source_partition, error.source_offset);
+            for new_error in errors {
+                let message = new_error.into_message(&error.raw_pgn, &error.source_topic, error.source_partition, error.source_offset);
+                producer.send(
+                    FutureRecord::to(failed_topic)
+                        .payload(&message.encode_to_vec())
+                        .key(&key),
+                    Duration::from_secs(0)
+                ).await.unwrap();
+            }
+            false
+        },
+    }
+}
+
+fn log_summary(summary: &BTreeMap<String, ErrorKindSummary>) {
+    for (kind, kind_summary) in summary {
+        info!("{}: {} resolved, {} still failing", kind, kind_summary.resolved, kind_summary.unresolved);
+    }
+}
@@ -0,0 +1,156 @@
+use {
+    std::sync::Arc,
+    tracing::{info, error},
+    clap::ValueEnum,
+    futures::future::join_all,
+    bigdata_chess_core::{
+        config::{Config, StepsConfig},
+        database::Database,
+        lichess::Lichess,
+        queue::Queue,
+        storage::Storage,
+    },
+    crate::{
+        chunk_splitter::chunk_splitter_step,
+        file_downloader::file_downloader_step,
+        game_parser::game_parser_step,
+        glicko2_ratings::glicko2_ratings_step,
+        hdfs_import::hdfs_import_step,
+        move_judgement::move_judgement_step,
+        opening_tree::opening_tree_step,
+        position_index::position_index_step,
+        postgres_import::postgres_import_step,
+        replay_parser_errors::replay_parser_errors_step,
+        storage_import::storage_import_step,
+        update_checker::update_checker_step,
+    },
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    UpdateChecker,
+    FileDownloader,
+    ChunkSplitter,
+    GameParser,
+    ReplayParserErrors,
+    PostgresImport,
+    StorageImport,
+    MoveJudgement,
+    OpeningTree,
+    PositionIndex,
+    Glicko2Ratings,
+    HdfsImport,
+}
+
+// handles shared by all steps running in this process. Each one is only created if some selected step needs it,
+// so that (for example) hdfs import does not require kafka or postgres to be reachable.
+#[derive(Clone)]
+struct Handles {
+    queue: Option<Arc<Queue>>,
+    storage: Option<Arc<Storage>>,
+    database: Option<Arc<Database>>,
+    lichess: Option<Arc<Lichess>>,
+}
+
+impl Step {
+    pub fn is_enabled(&self, config: &StepsConfig) -> bool {
+        match self {
+            Self::UpdateChecker => config.update_checker.enabled,
+            Self::FileDownloader => config.file_downloader().enabled,
+            Self::ChunkSplitter => config.chunk_splitter().enabled,
+            Self::GameParser => config.game_parser.enabled,
+            Self::ReplayParserErrors => config.replay_parser_errors.enabled,
+            Self::PostgresImport => config.postgres_import.enabled,
+            Self::StorageImport => config.storage_import.enabled,
+            Self::MoveJudgement => config.move_judgement.enabled,
+            Self::OpeningTree => config.opening_tree.enabled,
+            Self::PositionIndex => config.position_index.enabled,
+            Self::Glicko2Ratings => config.glicko2_ratings.enabled,
+            Self::HdfsImport => config.hdfs_import().enabled,
+        }
+    }
+
+    fn needs_queue(&self) -> bool {
+        !matches!(self, Self::HdfsImport)
+    }
+
+    fn needs_storage(&self) -> bool {
+        matches!(self, Self::UpdateChecker | Self::FileDownloader | Self::ChunkSplitter | Self::StorageImport | Self::MoveJudgement | Self::PositionIndex | Self::Glicko2Ratings | Self::HdfsImport)
+    }
+
+    fn needs_database(&self) -> bool {
+        matches!(self, Self::PostgresImport)
+    }
+
+    fn needs_lichess(&self) -> bool {
+        matches!(self, Self::UpdateChecker | Self::FileDownloader)
+    }
+}
+
+pub fn enabled_steps(config: &StepsConfig) -> Vec<Step> {
+    Step::value_variants()
+        .iter()
+        .filter(|step| step.is_enabled(config))
+        .cloned()
+        .collect()
+}
+
+pub async fn run_steps(config: Arc<Config>, steps: Vec<Step>) {
+    if steps.is_empty() {
+        info!("no steps to run");
+        return;
+    }
+
+    info!("running steps: {:?}", steps);
+
+    let infra = config.infra();
+    let handles = Handles {
+        queue: steps.iter().any(Step::needs_queue).then(|| Arc::new(Queue::new(&infra.queue()))),
+        storage: steps.iter().any(Step::needs_storage).then(|| Arc::new(Storage::new(&infra.storage()))),
+        database: if steps.iter().any(Step::needs_database) {
+            Some(Arc::new(Database::new(infra.database()).await))
+        } else {
+            None
+        },
+        lichess: steps.iter().any(Step::needs_lichess).then(|| Arc::new(Lichess::new())),
+    };
+
+    let tasks = steps.iter()
+        .map(|step| tokio::spawn(run_step(*step, config.clone(), handles.clone())));
+
+    for (step, result) in steps.iter().zip(join_all(tasks).await) {
+        match result {
+            Ok(Ok(())) => info!("step {:?} finished", step),
+            Ok(Err(err)) => error!("step {:?} failed: {}", step, err),
+            Err(err) => error!("step {:?} panicked: {}", step, err),
+        }
+    }
+}
+
+async fn run_step(step: Step, config: Arc<Config>, handles: Handles) -> std::io::Result<()> {
+    let steps = &config.steps;
+
+    match step {
+        Step::UpdateChecker => update_checker_step(&steps.update_checker, handles.lichess.unwrap(), handles.storage.unwrap(), handles.queue.unwrap()).await,
+        Step::FileDownloader => file_downloader_step(&steps.file_downloader(), handles.lichess.unwrap(), handles.storage.unwrap(), handles.queue.unwrap()).await,
+        Step::ChunkSplitter => chunk_splitter_step(&steps.chunk_splitter(), handles.storage.unwrap(), handles.queue.unwrap()).await,
+        Step::GameParser => game_parser_step(&steps.game_parser, handles.queue.unwrap()).await,
+        Step::ReplayParserErrors => replay_parser_errors_step(&steps.replay_parser_errors, handles.queue.unwrap()).await,
+        Step::PostgresImport => {
+            postgres_import_step(handles.queue.unwrap(), handles.database.unwrap()).await;
+            Ok(())
+        },
+        Step::StorageImport => {
+            storage_import_step(&steps.storage_import, handles.queue.unwrap(), handles.storage.unwrap()).await;
+            Ok(())
+        },
+        Step::MoveJudgement => move_judgement_step(&steps.move_judgement, handles.queue.unwrap(), handles.storage.unwrap()).await,
+        Step::OpeningTree => opening_tree_step(&steps.opening_tree, handles.queue.unwrap()).await,
+        Step::PositionIndex => position_index_step(&steps.position_index, handles.queue.unwrap(), handles.storage.unwrap()).await,
+        Step::Glicko2Ratings => glicko2_ratings_step(&steps.glicko2_ratings, handles.queue.unwrap(), handles.storage.unwrap()).await,
+        Step::HdfsImport => {
+            hdfs_import_step(steps.hdfs_import(), handles.storage.unwrap()).await;
+            Ok(())
+        },
+    }
+}
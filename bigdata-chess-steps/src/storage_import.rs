@@ -5,32 +5,37 @@ use {
     tracing::info,
     rdkafka::Message,
     prost::Message as ProstMessage,
-    rand::{Rng, distributions::Alphanumeric},
     bigdata_chess_core::{
-        queue::{Queue, TOPIC_CHESS_GAMES},
-        storage::Storage,
-        entity::{into_chess_game_entity, into_chess_game_move_entity, into_chess_game_comment_eval_entity},
+        config::StorageImportStepConfig,
+        queue::Queue,
+        storage::{Storage, GAME_DATA_GAMES, GAME_DATA_MOVES, GAME_DATA_COMMENTS_EVAL, GAME_DATA_CLOCKS},
+        entity::{into_chess_game_entity, into_chess_game_move_entity, into_chess_game_comment_eval_entity, into_chess_game_clock_entities},
+        eval::{Eval, Side},
         data::ChessGame,
     },
-    crate::progress::Progress,
+    crate::{
+        progress::Progress,
+        data_file::write_csv_data_file,
+    },
 };
 
 const GAMES_PER_FILE: u64 = 320_000;
 const MOVES_PER_FILE: u64 = GAMES_PER_FILE * 6;
 
-#[allow(dead_code)] // used from other crate
-pub async fn storage_import_step(queue: Arc<Queue>, storage: Arc<Storage>) {
-    info!("running storage import step");
+pub async fn storage_import_step(config: &StorageImportStepConfig, queue: Arc<Queue>, storage: Arc<Storage>) {
+    let variant = config.variant();
+    info!("running storage import step for {} games", variant.name());
 
     let consumer = queue.consumer_for_topic(
-        "bigdata-chess-storage-import",
-        TOPIC_CHESS_GAMES,
+        &config.group_id(),
+        &config.from_topic(),
     );
 
     let mut progress = Progress::new("processing games".to_owned());
     let mut games = Vec::new();
     let mut moves = Vec::new();
     let mut comment_evals = Vec::new();
+    let mut clocks = Vec::new();
 
     let mut time_total: f64 = 0.0;
 
@@ -41,21 +46,28 @@ pub async fn storage_import_step(queue: Arc<Queue>, storage: Arc<Storage>) {
         let payload = msg.payload().unwrap();
 
         let game = ChessGame::decode(payload).unwrap();
-        let game_id = base64::encode(msg.key().unwrap());
-
+        // games parsed before game ids were introduced only have random key
+        let game_id = if game.game_id.is_empty() {
+            base64::encode(msg.key().unwrap())
+        } else {
+            game.game_id.clone()
+        };
+
+        let first_to_move = Side::first_to_move(&game.starting_fen);
         let mut entry_index = 0;
+        let mut ply = 0;
         for entry in &game.game_entries {
             entry_index += 1;
             if let Some(san) = &entry.san {
-                if let Some(normal) = &san.normal {
-                    moves.push(into_chess_game_move_entity(&game_id,  entry_index,&normal, san.is_check.unwrap_or(false), san.is_checkmate.unwrap_or(false)));
-                }
+                ply += 1;
+                moves.push(into_chess_game_move_entity(&game_id, entry_index, san, entry.board_move.as_ref()));
             } else if let Some(comment) = &entry.comment {
-                if let Some(eval) = comment.eval {
-                    comment_evals.push(into_chess_game_comment_eval_entity(&game_id, entry_index, eval));
+                if let Some(eval) = Eval::from_comment(comment, Side::to_move_after(ply, first_to_move)) {
+                    comment_evals.push(into_chess_game_comment_eval_entity(&game_id, entry_index, ply, &eval));
                 }
             }
         }
+        clocks.append(&mut into_chess_game_clock_entities(&game_id, &game));
         games.push(into_chess_game_entity(game_id, game));
 
         if progress.update() {
@@ -63,78 +75,21 @@ pub async fn storage_import_step(queue: Arc<Queue>, storage: Arc<Storage>) {
         }
         
         while games.len() > GAMES_PER_FILE as usize {
-            let output_data = {
-                let mut output_data = Vec::new();
-
-                {
-                    let mut csv_writer = csv::WriterBuilder::new()
-                        .has_headers(false)
-                        .from_writer(&mut output_data);
-                    for game in &games {
-                        csv_writer.serialize(&game).unwrap();
-                    }
-                    games.clear();
-                }   
-
-                output_data
-            };
-            
-            let key = generate_game_data_file_key();
-            storage.put_game_data_file(&key, output_data).await;
-            info!("uploaded game data file with key: {}", key);
+            write_csv_data_file(&storage, variant, GAME_DATA_GAMES, &games).await;
+            games.clear();
         }
 
         while moves.len() > MOVES_PER_FILE as usize {
-            let output_data = {
-                let mut output_data = Vec::new();
-
-                {
-                    let mut csv_writer = csv::WriterBuilder::new()
-                        .has_headers(false)
-                        .from_writer(&mut output_data);
-                    for game_move in &moves {
-                        csv_writer.serialize(&game_move).unwrap();
-                    }
-                    moves.clear();
-                }
+            write_csv_data_file(&storage, variant, GAME_DATA_MOVES, &moves).await;
+            moves.clear();
 
-                output_data
-            };
+            write_csv_data_file(&storage, variant, GAME_DATA_COMMENTS_EVAL, &comment_evals).await;
+            comment_evals.clear();
 
-            let key = generate_game_data_file_key();
-            storage.put_game_moves_data_file(&key, output_data).await;
-            info!("uploaded game moves data file with key: {}", key);
-
-            // comments eval
-            let output_data = {
-                let mut output_data = Vec::new();
-
-                {
-                    let mut csv_writer = csv::WriterBuilder::new()
-                        .has_headers(false)
-                        .from_writer(&mut output_data);
-                    for comment_eval in &comment_evals {
-                        csv_writer.serialize(&comment_eval).unwrap();
-                    }
-                    comment_evals.clear();
-                }
-
-                output_data
-            };
-
-            let key = generate_game_data_file_key();
-            storage.put_game_comment_eval_data_file(&key, output_data).await;
-            info!("uploaded game eval comments data file with key: {}", key);
+            write_csv_data_file(&storage, variant, GAME_DATA_CLOCKS, &clocks).await;
+            clocks.clear();
         }
 
         time_total += (Instant::now() - started_at).as_secs_f64();
     }
 }
-
-fn generate_game_data_file_key() -> String {
-    rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(16)
-        .map(char::from)
-        .collect()
-}
\ No newline at end of file
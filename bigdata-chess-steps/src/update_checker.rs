@@ -1,38 +1,105 @@
 use {
     std::{time::Duration, sync::Arc},
-    tracing::info,
-    rdkafka::{producer::{FutureProducer, FutureRecord}},
+    tracing::{info, warn},
+    rdkafka::producer::FutureRecord,
     bigdata_chess_core::{
         config::UpdateCheckerStepConfig,
-        lichess::Lichess,
-        queue::Queue,
+        lichess::{Lichess, LichessDataFile, LichessDataFileInfo},
+        queue::{Queue, LichessDataFileMessage, TOPIC_LICHESS_DATA_FILES},
+        storage::Storage,
     },
 };
 
-#[allow(dead_code)] // used from other crate
-pub async fn update_checker_step(config: &UpdateCheckerStepConfig, kafka_endpoint: String, lichess: Lichess, queue: Arc<Queue>) -> std::io::Result<()> {
-    if !config.enabled {
-        return Ok(());
-    }
-    
+pub async fn update_checker_step(config: &UpdateCheckerStepConfig, lichess: Arc<Lichess>, storage: Arc<Storage>, queue: Arc<Queue>) -> std::io::Result<()> {
+    info!("running update checker step");
+
     let producer = queue.producer();
 
     loop {
-        info!("fetching files list from lichess");
-        let lichess_data_files = enforce_lichess_download_limit(&lichess.files_from_lichess_database_list().await);
-        info!("lichess data files: {:?}", lichess_data_files);
+        // newest files and total bytes limits apply to files of all variants together
+        let mut files = Vec::new();
+        for variant in config.variants() {
+            info!("fetching files list from lichess for {}", variant.name());
+            files.append(&mut lichess.files_from_lichess_database_list(variant).await);
+        }
+        let lichess_data_files = select_lichess_data_files(config, &lichess, &files).await;
+        info!("lichess data files: {:?}", lichess_data_files.iter().map(|(file, _)| file.file_name()).collect::<Vec<_>>());
+
+        let mut announced_files = storage.get_announced_lichess_data_files().await;
+
+        for (file, info) in lichess_data_files {
+            let changed = match announced_files.get(&file.url) {
+                None => false,
+                Some(announced) if announced != &info => true,
+                Some(_) => continue,
+            };
 
-        for file in lichess_data_files {
-            producer.send(FutureRecord::to("chess-lichess-data-files").payload(&file).key(&file), Duration::from_secs(0)).await.unwrap();
+            if changed {
+                info!("lichess data file was re-uploaded: {}", file.file_name());
+            } else {
+                info!("new lichess data file: {}", file.file_name());
+            }
+
+            let message = LichessDataFileMessage::new(file.url.clone(), info.clone(), changed);
+            producer.send(
+                FutureRecord::to(TOPIC_LICHESS_DATA_FILES)
+                    .payload(&serde_json::to_vec(&message).unwrap())
+                    .key(&file.url),
+                Duration::from_secs(0)
+            ).await.unwrap();
+
+            announced_files.insert(file.url, info);
+            storage.put_announced_lichess_data_files(&announced_files).await;
         }
 
         info!("sleeping before checking for updates again");
         tokio::time::sleep(Duration::from_secs(60 * 60)).await;
     }
 }
-fn enforce_lichess_download_limit(files: &Vec<String>) -> Vec<String> {
-    files.iter()
-        .take(1)
-        .cloned()
-        .collect()
+
+// info of selected files is needed anyway to find out whether they were re-uploaded
+async fn select_lichess_data_files(config: &UpdateCheckerStepConfig, lichess: &Lichess, files: &Vec<String>) -> Vec<(LichessDataFile, LichessDataFileInfo)> {
+    let mut files: Vec<LichessDataFile> = files.iter()
+        .filter_map(|url| match LichessDataFile::from_url(url) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                warn!("skipping lichess data file: {}", err);
+                None
+            },
+        })
+        .filter(|file| config.from_month().map(|from| &file.date >= from).unwrap_or(true))
+        .filter(|file| config.to_month().map(|to| &file.date <= to).unwrap_or(true))
+        .filter(|file| config.allow_list().map(|allow_list| allow_list.iter().any(|v| v == file.file_name())).unwrap_or(true))
+        .collect();
+
+    files.sort_by(|a, b| b.date.cmp(&a.date));
+
+    if let Some(newest) = config.newest() {
+        files.truncate(newest);
+    }
+
+    // files are taken newest first until the total size limit is reached
+    let mut selected = Vec::new();
+    let mut total_bytes = 0;
+    for file in files {
+        let info = match lichess.file_info(&file.url).await {
+            Ok(v) => v,
+            Err(err) => {
+                warn!("failed to get info for lichess data file, will retry later: {}", err);
+                continue;
+            },
+        };
+
+        if let Some(max_total_bytes) = config.max_total_bytes() {
+            if total_bytes + info.size > max_total_bytes {
+                info!("reached limit of total bytes to download at {}", file.file_name());
+                break;
+            }
+        }
+
+        total_bytes += info.size;
+        selected.push((file, info));
+    }
+
+    selected
 }
@@ -41,7 +41,7 @@ async fn main() -> std::io::Result<()> {
 
     info!("bigdata nlp computer workshop");
 
-    let config = Config::load();
+    let config = Config::load().unwrap();
     let queue = Arc::new(Queue::new(&config.infra().queue())); 
 
     // load_data_files(queue).await;
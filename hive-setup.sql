@@ -16,7 +16,22 @@ CREATE TABLE chess_games(
     opening string,
     timecontrol_duration int,
     timecontrol_increment int,
-    termination int
+    termination int,
+    variant string,
+    starting_fen string,
+    annotator string,
+    white_player_fide_id int,
+    black_player_fide_id int,
+    extra_headers string,
+    white_player_accuracy double,
+    black_player_accuracy double,
+    opening_family string,
+    opening_variation string,
+    opening_ply int,
+    rated boolean,
+    speed string,
+    tournament_id string,
+    swiss_id string
 )
 PARTITIONED BY(day string)
 ROW FORMAT SERDE 'org.apache.hadoop.hive.serde2.OpenCSVSerde'
@@ -33,7 +48,12 @@ create table chess_game_moves(
     capture boolean,
     promotion tinyint,
     is_check boolean,
-    is_checkmate boolean
+    is_checkmate boolean,
+    ply int,
+    role tinyint,
+    uci string,
+    san string,
+    fen_after string
 )
 clustered by (game_id) into 24 buckets
 row format serde 'org.apache.hadoop.hive.serde2.OpenCSVSerde'
@@ -43,9 +63,70 @@ LOCATION '/tables_data/chess_game_moves';
 create table chess_game_comments_eval(
     game_id string,
     move_id int,
-    eval float
+    eval float,
+    ply int,
+    centipawns int,
+    mate_in int,
+    side_to_move string,
+    win_probability double
 )
 clustered by (game_id) into 24 buckets
 row format serde 'org.apache.hadoop.hive.serde2.OpenCSVSerde'
 STORED AS TEXTFILE
-LOCATION '/tables_data/chess_game_comments_eval';
\ No newline at end of file
+LOCATION '/tables_data/chess_game_comments_eval';
+
+create table chess_game_clocks(
+    game_id string,
+    ply int,
+    side string,
+    clock_remaining int,
+    time_spent int
+)
+clustered by (game_id) into 24 buckets
+row format serde 'org.apache.hadoop.hive.serde2.OpenCSVSerde'
+STORED AS TEXTFILE
+LOCATION '/tables_data/chess_game_clocks';
+
+create table chess_game_move_judgements(
+    game_id string,
+    move_id int,
+    ply int,
+    side string,
+    judgement string,
+    win_probability_before double,
+    win_probability_after double,
+    winning_chances_loss double
+)
+clustered by (game_id) into 24 buckets
+row format serde 'org.apache.hadoop.hive.serde2.OpenCSVSerde'
+STORED AS TEXTFILE
+LOCATION '/tables_data/chess_game_move_judgements';
+
+create table chess_game_positions(
+    game_id string,
+    ply int,
+    position_hash bigint
+)
+clustered by (position_hash) into 24 buckets
+row format serde 'org.apache.hadoop.hive.serde2.OpenCSVSerde'
+STORED AS TEXTFILE
+LOCATION '/tables_data/chess_game_positions';
+
+create table chess_game_ratings(
+    game_id string,
+    game_date int,
+    speed string,
+    side string,
+    player_name string,
+    rating_before double,
+    rating_after double,
+    deviation_before double,
+    deviation_after double,
+    volatility_after double,
+    lichess_rating int,
+    lichess_rating_diff int
+)
+clustered by (player_name) into 24 buckets
+row format serde 'org.apache.hadoop.hive.serde2.OpenCSVSerde'
+STORED AS TEXTFILE
+LOCATION '/tables_data/chess_game_ratings';
\ No newline at end of file
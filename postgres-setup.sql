@@ -0,0 +1,27 @@
+create table chess_games(
+    id text primary key,
+    opening text,
+    white_player_elo int,
+    white_player_accuracy double precision, -- null for games without evaluations
+    black_player_accuracy double precision,
+    rated boolean,
+    speed text, -- ultraBullet, bullet, blitz, rapid, classical or correspondence
+    tournament_id text,
+    swiss_id text
+);
+
+create table chess_game_moves(
+    id text primary key, -- game_id:move_id
+    game_id text not null,
+    from_file smallint,
+    from_rank smallint,
+    to_file smallint,
+    to_rank smallint,
+    ply int,
+    role smallint,
+    uci text,
+    san text,
+    fen_after text
+);
+
+create index chess_game_moves_game_id on chess_game_moves(game_id);
@@ -2,7 +2,17 @@
 rpk topic create chess-lichess-data-files -c cleanup.policy=compact -r 1 -p 24
 rpk topic create chess-lichess-data-files-synced -r 1 -p 24
 rpk topic create chess-lichess-raw-games -r 1 -p 24
+rpk topic create chess-lichess-raw-games-rejects -r 1 -p 1
+rpk topic create chess-chunk-splitter-state -c cleanup.policy=compact -r 1 -p 1
 rpk topic create chess-lichess-raw-games-blue -r 1 -p 24
 rpk topic create chess-games -r 1 -p 24
+for variant in chess960 crazyhouse antichess atomic horde kingOfTheHill racingKings threeCheck; do
+  rpk topic create chess-lichess-raw-games-$variant -r 1 -p 24
+  rpk topic create chess-lichess-raw-games-rejects-$variant -r 1 -p 1
+  rpk topic create chess-games-$variant -r 1 -p 24
+done
+rpk topic create chess-game-judgements -r 1 -p 24
+rpk topic create chess-game-ratings -r 1 -p 24
 rpk topic create chess-game-parser-errors -r 1 -p 1
+rpk topic create chess-game-parser-errors-unresolved -r 1 -p 1
 rpk topic create chess-logs -r 1 -p 1
\ No newline at end of file